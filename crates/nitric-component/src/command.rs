@@ -0,0 +1,841 @@
+//! Deferred structural changes.
+//!
+//! Creating and deleting IDs or inserting and removing components requires
+//! mutable access to the allocator and the storages, which parallel systems
+//! usually do not have. A `CommandBuffer` records these operations through a
+//! shared reference instead, and replays them later with
+//! `CommandBuffer::apply`.
+//!
+//! Deleted IDs are only flagged for deletion, so they have to be merged
+//! afterwards, like IDs deleted with `Delete::delete`.
+//!
+//! ```
+//! use nitric_component::{
+//!     allocator::Create,
+//!     command::CommandBuffer,
+//!     allocator::MergeDeleted,
+//!     id::MergingDeletion,
+//!     impls::{FlatAllocator, FlatUsize},
+//!     storage::Storage,
+//! };
+//!
+//! let (mut alloc, mut merger) = FlatAllocator::new();
+//! let mut names = Storage::<FlatUsize, &str>::new();
+//! let mut speeds = Storage::<FlatUsize, f32>::new();
+//! let existing = alloc.create().unwrap();
+//!
+//! let mut buffer = CommandBuffer::new();
+//! let spawned = buffer.create();
+//! buffer.insert(spawned, "spawned");
+//! buffer.insert(spawned, 2.0f32);
+//! buffer.delete(existing);
+//!
+//! let applied = buffer
+//!     .apply(&mut alloc, &mut merger, (&mut names, &mut speeds))
+//!     .unwrap();
+//!
+//! let spawned = applied.resolve(spawned).unwrap().checked(&alloc, &merger).unwrap();
+//! assert_eq!(names.get(&spawned), Some(&"spawned"));
+//! assert_eq!(applied.deleted(), [existing]);
+//!
+//! alloc.merge_deleted(&mut merger);
+//! assert!(existing.checked(&alloc, &merger).is_err());
+//! ```
+
+use std::{
+    any::{type_name, Any, TypeId},
+    fmt::{self, Debug, Formatter},
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    allocator::{Create, Delete},
+    error::ApplyError,
+    id::{CheckedId, Id, MergingDeletion, SparseLinear},
+    storage::Storage,
+};
+
+static NEXT_BUFFER: AtomicUsize = AtomicUsize::new(0);
+
+/// Stands in for an ID which will be created when the `CommandBuffer` it
+/// was returned from is applied. See `Applied::resolve`.
+///
+/// A placeholder is only valid for the next `apply` of its buffer; using it
+/// with another buffer or after that makes `apply` panic.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Placeholder {
+    /// The ID of the buffer.
+    buffer: usize,
+    /// The number of times the buffer had been applied or cleared before.
+    generation: u64,
+    index: usize,
+}
+
+/// The ID a command refers to; either an existing ID or a `Placeholder`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Target<ID> {
+    /// An existing ID.
+    Id(ID),
+    /// An ID created by the same buffer.
+    Placeholder(Placeholder),
+}
+
+impl<ID: Id> From<ID> for Target<ID> {
+    fn from(id: ID) -> Self {
+        Target::Id(id)
+    }
+}
+
+impl<ID: Id> From<Placeholder> for Target<ID> {
+    fn from(placeholder: Placeholder) -> Self {
+        Target::Placeholder(placeholder)
+    }
+}
+
+/// A set of storages commands can be applied to, usually a tuple of
+/// `&mut Storage`s.
+pub trait Storages<ID>
+where
+    ID: SparseLinear + MergingDeletion,
+{
+    /// Returns the storage with the `TypeId` `storage`, if it is part of this
+    /// set.
+    fn storage(&mut self, storage: TypeId) -> Option<&mut dyn Any>;
+
+    /// Removes the components of `id` from all storages of this set.
+    fn remove_all(&mut self, id: &CheckedId<'_, ID>);
+}
+
+impl<ID, C> Storages<ID> for Storage<ID, C>
+where
+    ID: SparseLinear + MergingDeletion + 'static,
+    C: 'static,
+{
+    fn storage(&mut self, storage: TypeId) -> Option<&mut dyn Any> {
+        match storage == TypeId::of::<Self>() {
+            true => Some(self),
+            false => None,
+        }
+    }
+
+    fn remove_all(&mut self, id: &CheckedId<'_, ID>) {
+        self.remove(id);
+    }
+}
+
+impl<ID, S> Storages<ID> for &mut S
+where
+    ID: SparseLinear + MergingDeletion,
+    S: Storages<ID> + ?Sized,
+{
+    fn storage(&mut self, storage: TypeId) -> Option<&mut dyn Any> {
+        (**self).storage(storage)
+    }
+
+    fn remove_all(&mut self, id: &CheckedId<'_, ID>) {
+        (**self).remove_all(id)
+    }
+}
+
+macro_rules! impl_storages {
+    ($($s:ident),*) => {
+        impl<ID, $($s),*> Storages<ID> for ($($s,)*)
+        where
+            ID: SparseLinear + MergingDeletion,
+            $($s: Storages<ID>,)*
+        {
+            #[allow(non_snake_case, unused)]
+            fn storage(&mut self, storage: TypeId) -> Option<&mut dyn Any> {
+                let ($($s,)*) = self;
+
+                None$(.or_else(move || $s.storage(storage)))*
+            }
+
+            #[allow(non_snake_case, unused)]
+            fn remove_all(&mut self, id: &CheckedId<'_, ID>) {
+                let ($($s,)*) = self;
+
+                $($s.remove_all(id);)*
+            }
+        }
+    };
+}
+
+impl_storages!();
+impl_storages!(A);
+impl_storages!(A, B);
+impl_storages!(A, B, C);
+impl_storages!(A, B, C, D);
+impl_storages!(A, B, C, D, E);
+impl_storages!(A, B, C, D, E, F);
+impl_storages!(A, B, C, D, E, F, G);
+impl_storages!(A, B, C, D, E, F, G, H);
+
+type ComponentFn<ID> = Box<dyn FnOnce(&mut dyn Any, &CheckedId<'_, ID>) + Send>;
+
+enum Command<ID: Id + MergingDeletion> {
+    Create,
+    Delete(Target<ID>),
+    Component {
+        target: Target<ID>,
+        /// The `TypeId` of the `Storage` the command applies to.
+        storage: TypeId,
+        /// The name of the component type.
+        type_name: &'static str,
+        apply: ComponentFn<ID>,
+    },
+}
+
+struct Recorded<ID: Id + MergingDeletion> {
+    commands: Vec<Command<ID>>,
+    created: usize,
+    generation: u64,
+}
+
+/// Records structural changes from any number of threads, to be applied
+/// later in the order they were submitted.
+pub struct CommandBuffer<ID: Id + MergingDeletion> {
+    id: usize,
+    recorded: Mutex<Recorded<ID>>,
+}
+
+impl<ID> CommandBuffer<ID>
+where
+    ID: SparseLinear + MergingDeletion + Send + 'static,
+{
+    /// Creates an empty command buffer.
+    pub fn new() -> Self {
+        CommandBuffer {
+            id: NEXT_BUFFER.fetch_add(1, Ordering::Relaxed),
+            recorded: Mutex::new(Recorded {
+                commands: Vec::new(),
+                created: 0,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Records the creation of an ID, returning a placeholder for it which
+    /// can be used by the following commands.
+    pub fn create(&self) -> Placeholder {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.commands.push(Command::Create);
+        recorded.created += 1;
+
+        Placeholder {
+            buffer: self.id,
+            generation: recorded.generation,
+            index: recorded.created - 1,
+        }
+    }
+
+    /// Records the deletion of `target`, flagging it for deletion.
+    ///
+    /// Components of the deleted ID are removed from the storages passed to
+    /// `apply`.
+    pub fn delete(&self, target: impl Into<Target<ID>>) {
+        self.push(Command::Delete(target.into()));
+    }
+
+    /// Records the insertion of `component` for `target`, replacing any
+    /// existing component.
+    pub fn insert<C>(&self, target: impl Into<Target<ID>>, component: C)
+    where
+        C: Send + 'static,
+    {
+        self.push_component::<C>(
+            target.into(),
+            Box::new(move |storage, id| {
+                downcast::<ID, C>(storage).insert(id.clone(), component);
+            }),
+        );
+    }
+
+    /// Records the removal of the component of type `C` of `target`.
+    pub fn remove<C>(&self, target: impl Into<Target<ID>>)
+    where
+        C: Send + 'static,
+    {
+        self.push_component::<C>(
+            target.into(),
+            Box::new(|storage, id| {
+                downcast::<ID, C>(storage).remove(id);
+            }),
+        );
+    }
+
+    /// Returns the number of recorded commands.
+    pub fn len(&self) -> usize {
+        self.recorded.lock().unwrap().commands.len()
+    }
+
+    /// Checks if no commands have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Discards all recorded commands.
+    pub fn clear(&mut self) {
+        self.take();
+    }
+
+    /// Replays all recorded commands in the order they were submitted,
+    /// leaving the buffer empty.
+    ///
+    /// Placeholders are resolved to newly created IDs, which are returned as
+    /// part of `Applied`. Commands referring to invalid IDs are skipped.
+    /// Once all commands are applied, the components of the IDs deleted by
+    /// this buffer are removed from `storages`.
+    ///
+    /// Deleted IDs are only flagged for deletion; merging them with
+    /// `MergeDeleted::merge_deleted` is up to the caller. This way, IDs
+    /// flagged outside of the buffer are never deleted without their
+    /// components being removed.
+    ///
+    /// # Errors
+    ///
+    /// Fails if an ID cannot be created. Commands submitted before the
+    /// failing creation stay applied, the remaining ones are discarded. The
+    /// components of the IDs deleted so far are removed all the same, and the
+    /// returned `ApplyError` carries their `Applied`.
+    ///
+    /// # Panics
+    ///
+    /// Panics before applying anything if
+    ///
+    /// * a component command refers to a storage which is not part of
+    ///   `storages`, or
+    /// * a command refers to a placeholder returned by another buffer, or by
+    ///   this buffer before it was last applied or cleared.
+    pub fn apply<S>(
+        &mut self,
+        alloc: &mut ID::Allocator,
+        merger: &mut ID::Merger,
+        mut storages: S,
+    ) -> Result<Applied<ID>, ApplyError<ID>>
+    where
+        ID::Allocator: Create<ID> + Delete<ID>,
+        S: Storages<ID>,
+    {
+        let (generation, created) = {
+            let recorded = self.recorded.get_mut().unwrap();
+
+            (recorded.generation, recorded.created)
+        };
+        let commands = self.take();
+
+        for command in &commands {
+            let target = match *command {
+                Command::Create => continue,
+                Command::Delete(ref target) => target,
+                Command::Component {
+                    ref target,
+                    storage,
+                    type_name,
+                    ..
+                } => {
+                    if storages.storage(storage).is_none() {
+                        panic!("No storage for component `{}` was passed", type_name);
+                    }
+
+                    target
+                }
+            };
+
+            if let Target::Placeholder(placeholder) = *target {
+                assert!(
+                    placeholder.buffer == self.id
+                        && placeholder.generation == generation
+                        && placeholder.index < created,
+                    "Placeholder does not belong to this buffer or has been applied already",
+                );
+            }
+        }
+
+        let mut applied = Applied {
+            buffer: self.id,
+            generation,
+            created: Vec::with_capacity(created),
+            deleted: Vec::new(),
+            invalid: Vec::new(),
+        };
+        // All IDs deleted by this buffer, including ones flagged already
+        let mut deleted = Vec::new();
+        let mut oom = false;
+
+        for command in commands {
+            match command {
+                Command::Create => match alloc.create() {
+                    Ok(id) => applied.created.push(id),
+                    Err(_) => {
+                        oom = true;
+                        break;
+                    }
+                },
+                Command::Delete(target) => {
+                    let id = applied.target(target);
+                    match id.clone().checked(alloc, merger) {
+                        Ok(checked) => {
+                            if !alloc.is_flagged(&checked) {
+                                alloc.delete(&checked);
+                                applied.deleted.push(id.clone());
+                            }
+                            deleted.push(id);
+                        }
+                        Err(e) => applied.invalid.push(e.0),
+                    }
+                }
+                Command::Component {
+                    target,
+                    storage,
+                    apply,
+                    ..
+                } => {
+                    let id = applied.target(target);
+                    match id.checked(alloc, merger) {
+                        Ok(checked) => {
+                            apply(storages.storage(storage).expect("Unreachable"), &checked)
+                        }
+                        Err(e) => applied.invalid.push(e.0),
+                    }
+                }
+            }
+        }
+
+        for id in deleted {
+            // Flagged IDs stay valid until they are merged
+            let checked = id.checked(alloc, merger).expect("Unreachable");
+            storages.remove_all(&checked);
+        }
+
+        match oom {
+            true => Err(ApplyError { applied }),
+            false => Ok(applied),
+        }
+    }
+
+    fn push(&self, command: Command<ID>) {
+        self.recorded.lock().unwrap().commands.push(command);
+    }
+
+    fn push_component<C: 'static>(&self, target: Target<ID>, apply: ComponentFn<ID>) {
+        self.push(Command::Component {
+            target,
+            storage: TypeId::of::<Storage<ID, C>>(),
+            type_name: type_name::<C>(),
+            apply,
+        });
+    }
+
+    fn take(&mut self) -> Vec<Command<ID>> {
+        let recorded = self.recorded.get_mut().unwrap();
+        recorded.created = 0;
+        recorded.generation += 1;
+
+        mem::take(&mut recorded.commands)
+    }
+}
+
+impl<ID> Debug for CommandBuffer<ID>
+where
+    ID: SparseLinear + MergingDeletion + Send + 'static,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandBuffer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<ID> Default for CommandBuffer<ID>
+where
+    ID: SparseLinear + MergingDeletion + Send + 'static,
+{
+    fn default() -> Self {
+        CommandBuffer::new()
+    }
+}
+
+fn downcast<ID, C>(storage: &mut dyn Any) -> &mut Storage<ID, C>
+where
+    ID: SparseLinear + 'static,
+    C: 'static,
+{
+    storage.downcast_mut().expect("Unreachable")
+}
+
+/// The result of `CommandBuffer::apply`.
+#[derive(Clone, Debug)]
+pub struct Applied<ID> {
+    buffer: usize,
+    generation: u64,
+    created: Vec<ID>,
+    deleted: Vec<ID>,
+    invalid: Vec<ID>,
+}
+
+impl<ID: Clone> Applied<ID> {
+    /// Returns the ID created for `placeholder`, or `None` if the
+    /// placeholder was not returned by the applied buffer since it was last
+    /// applied or cleared.
+    pub fn resolve(&self, placeholder: Placeholder) -> Option<&ID> {
+        match placeholder.buffer == self.buffer && placeholder.generation == self.generation {
+            true => self.created.get(placeholder.index),
+            false => None,
+        }
+    }
+
+    /// Returns the created IDs, in the order of their placeholders.
+    pub fn created(&self) -> &[ID] {
+        &self.created
+    }
+
+    /// Returns the IDs flagged for deletion by the buffer. IDs which had
+    /// been flagged already are not included.
+    pub fn deleted(&self) -> &[ID] {
+        &self.deleted
+    }
+
+    /// Returns the invalid IDs commands were skipped for.
+    pub fn invalid(&self) -> &[ID] {
+        &self.invalid
+    }
+
+    fn target(&self, target: Target<ID>) -> ID {
+        match target {
+            Target::Id(id) => id,
+            // Validated before applying anything
+            Target::Placeholder(placeholder) => self.created[placeholder.index].clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, panic, thread};
+
+    use super::*;
+    use crate::{
+        allocator::{Allocator, CreateChecked, MergeDeleted, Merger},
+        error::{InvalidIdError, OomError},
+        id::{ValidId, WrapperId},
+        impls::{FlatAllocator, FlatUsize, UsizeAllocator},
+    };
+
+    /// An ID whose allocator runs out after a fixed number of IDs.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct LimitedId(FlatUsize);
+
+    struct LimitedAllocator {
+        inner: UsizeAllocator,
+        remaining: usize,
+    }
+
+    impl Id for LimitedId {
+        type Allocator = LimitedAllocator;
+        type Key = usize;
+
+        fn try_as_key(
+            &self,
+            allocator: &LimitedAllocator,
+        ) -> Result<Cow<'_, usize>, InvalidIdError<Self>> {
+            match allocator.is_valid(self) {
+                true => Ok(self.as_key_unchecked()),
+                false => Err(InvalidIdError(*self)),
+            }
+        }
+
+        fn as_key_unchecked(&self) -> Cow<'_, usize> {
+            self.0.as_key_unchecked()
+        }
+    }
+
+    impl WrapperId for LimitedId {
+        type Original = FlatUsize;
+
+        fn as_inner(&self) -> &FlatUsize {
+            &self.0
+        }
+
+        fn into_inner(self) -> FlatUsize {
+            self.0
+        }
+    }
+
+    impl Allocator<LimitedId> for LimitedAllocator {
+        fn is_valid(&self, id: &LimitedId) -> bool {
+            self.inner.is_valid(id.as_usize())
+        }
+
+        fn num_valid(&self) -> usize {
+            self.inner.num_valid()
+        }
+
+        fn num_valid_hint(&self) -> (usize, Option<usize>) {
+            self.inner.num_valid_hint()
+        }
+    }
+
+    impl Create<LimitedId> for LimitedAllocator {
+        fn create(&mut self) -> Result<LimitedId, OomError> {
+            self.remaining = self.remaining.checked_sub(1).ok_or(OomError)?;
+
+            self.inner.create().map(|id| LimitedId(FlatUsize::from(id)))
+        }
+    }
+
+    impl Delete<LimitedId> for LimitedAllocator {
+        fn is_flagged<V>(&self, id: &V) -> bool
+        where
+            V: ValidId<LimitedId>,
+        {
+            self.inner.is_flagged(id.as_inner().as_usize())
+        }
+
+        fn delete<V>(&mut self, id: &V)
+        where
+            V: ValidId<LimitedId>,
+        {
+            self.inner.delete_valid(id.as_inner().as_usize())
+        }
+
+        fn try_delete(&mut self, id: &LimitedId) -> Result<(), InvalidIdError<LimitedId>> {
+            self.inner
+                .try_delete(id.as_usize())
+                .map_err(|_| InvalidIdError(*id))
+        }
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Pos(u32);
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Vel(u32);
+
+    #[test]
+    fn apply() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+        let mut vel = Storage::<FlatUsize, Vel>::new();
+
+        let a = alloc.create().unwrap();
+        let b = alloc.create().unwrap();
+        vel.insert(b.checked(&alloc, &merger).unwrap(), Vel(1));
+
+        let mut buffer = CommandBuffer::new();
+        let c = buffer.create();
+        buffer.insert(c, Pos(3));
+        buffer.insert(a, Vel(2));
+        buffer.remove::<Vel>(b);
+        buffer.insert(b, Pos(1));
+        buffer.insert(c, Pos(4));
+        assert_eq!(buffer.len(), 6);
+
+        let applied = buffer
+            .apply(&mut alloc, &mut merger, (&mut pos, &mut vel))
+            .unwrap();
+        assert!(buffer.is_empty());
+
+        let a = a.checked(&alloc, &merger).unwrap();
+        let b = b.checked(&alloc, &merger).unwrap();
+        let c = applied
+            .resolve(c)
+            .unwrap()
+            .checked(&alloc, &merger)
+            .unwrap();
+        assert_eq!(applied.created(), [*c.id()]);
+        assert_eq!(pos.get(&c), Some(&Pos(4)));
+        assert_eq!(vel.get(&a), Some(&Vel(2)));
+        assert_eq!(vel.get(&b), None);
+        assert_eq!(pos.get(&b), Some(&Pos(1)));
+    }
+
+    #[test]
+    fn delete() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+        let mut vel = Storage::<FlatUsize, Vel>::new();
+
+        let a = alloc.create().unwrap();
+        let dead = alloc.create().unwrap();
+        alloc.delete(&dead.checked(&alloc, &merger).unwrap());
+        alloc.merge_deleted(&mut merger);
+
+        let mut buffer = CommandBuffer::new();
+        // Recorded before `b` is created, which recycles the dead ID
+        buffer.insert(dead, Pos(0));
+        let b = buffer.create();
+        buffer.insert(a, Pos(1));
+        buffer.insert(b, Vel(2));
+        buffer.delete(a);
+        buffer.delete(b);
+        buffer.delete(a);
+        // Still valid until the deletion is merged
+        buffer.insert(a, Vel(1));
+
+        let applied = buffer
+            .apply(&mut alloc, &mut merger, (&mut pos, &mut vel))
+            .unwrap();
+        let b = *applied.resolve(b).unwrap();
+
+        assert_eq!(applied.deleted(), [a, b]);
+        assert_eq!(applied.invalid(), [dead]);
+        assert_eq!(alloc.num_valid(), 2);
+
+        let mut merged = alloc.merge_deleted(&mut merger);
+        merged.sort();
+        assert_eq!(merged, [a, b]);
+        assert_eq!(alloc.num_valid(), 0);
+
+        // The recycled ID must not inherit any components
+        let recycled = alloc.create_checked(&merger).unwrap();
+        assert_eq!(pos.get(&recycled), None);
+        assert_eq!(vel.get(&recycled), None);
+    }
+
+    #[test]
+    fn threads() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+        let mut buffer = CommandBuffer::new();
+
+        thread::scope(|scope| {
+            for i in 0..4 {
+                let buffer = &buffer;
+                scope.spawn(move || {
+                    for _ in 0..25 {
+                        let id = buffer.create();
+                        buffer.insert(id, Pos(i));
+                    }
+                });
+            }
+        });
+
+        let applied = buffer.apply(&mut alloc, &mut merger, &mut pos).unwrap();
+        assert_eq!(applied.created().len(), 100);
+        assert_eq!(alloc.num_valid(), 100);
+
+        for id in applied.created() {
+            assert!(pos.get(&id.checked(&alloc, &merger).unwrap()).is_some());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "No storage for component")]
+    fn missing_storage() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+
+        let mut buffer = CommandBuffer::new();
+        let a = buffer.create();
+        buffer.insert(a, Vel(0));
+
+        let _ = buffer.apply(&mut alloc, &mut merger, (&mut pos,));
+    }
+
+    #[test]
+    fn foreign_deletion() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+
+        let a = alloc.create_checked(&merger).unwrap();
+        pos.insert(a, Pos(0));
+        let a = *a.id();
+        alloc.delete(&a.checked(&alloc, &merger).unwrap());
+
+        let mut buffer = CommandBuffer::new();
+        buffer.delete(a);
+        let applied = buffer.apply(&mut alloc, &mut merger, &mut pos).unwrap();
+
+        // Flagged outside of the buffer, and not merged by it
+        assert_eq!(applied.deleted(), []);
+        assert!(alloc.is_valid(&a));
+    }
+
+    #[test]
+    fn resolve_foreign() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+
+        let mut buffer = CommandBuffer::<FlatUsize>::new();
+        let other = CommandBuffer::<FlatUsize>::new();
+        let a = buffer.create();
+        let b = other.create();
+
+        let applied = buffer.apply(&mut alloc, &mut merger, ()).unwrap();
+        assert!(applied.resolve(a).is_some());
+        assert_eq!(applied.resolve(b), None);
+
+        buffer.create();
+        let applied = buffer.apply(&mut alloc, &mut merger, ()).unwrap();
+        assert_eq!(applied.resolve(a), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Placeholder does not belong to this buffer")]
+    fn foreign_placeholder() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+
+        let mut buffer = CommandBuffer::new();
+        let other = CommandBuffer::<FlatUsize>::new();
+        let a = other.create();
+        buffer.create();
+        buffer.insert(a, Pos(0));
+
+        let _ = buffer.apply(&mut alloc, &mut merger, &mut pos);
+    }
+
+    #[test]
+    fn stale_placeholder() {
+        let (mut alloc, mut merger) = FlatAllocator::new();
+        let mut pos = Storage::<FlatUsize, Pos>::new();
+
+        let mut buffer = CommandBuffer::new();
+        let a = buffer.create();
+        buffer.apply(&mut alloc, &mut merger, &mut pos).unwrap();
+
+        buffer.create();
+        buffer.insert(a, Pos(0));
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            buffer.apply(&mut alloc, &mut merger, &mut pos)
+        }));
+
+        // Nothing has been applied, not even the creation
+        assert!(result.is_err());
+        assert_eq!(alloc.num_valid(), 1);
+    }
+
+    #[test]
+    fn out_of_ids() {
+        let mut alloc = LimitedAllocator {
+            inner: UsizeAllocator::new(),
+            remaining: 2,
+        };
+        let mut merger = Merger::<FlatAllocator>::new();
+        let mut pos = Storage::<LimitedId, Pos>::new();
+
+        let a = alloc.create().unwrap();
+        pos.insert(a.checked(&alloc, &merger).unwrap(), Pos(0));
+
+        let mut buffer = CommandBuffer::new();
+        let b = buffer.create();
+        buffer.insert(b, Pos(1));
+        buffer.delete(a);
+        let c = buffer.create();
+        buffer.insert(c, Pos(2));
+
+        let err = buffer.apply(&mut alloc, &mut merger, &mut pos).unwrap_err();
+        let b = *err.applied.resolve(b).unwrap();
+        assert_eq!(err.applied.resolve(c), None);
+        assert_eq!(err.applied.deleted(), [a]);
+        assert!(buffer.is_empty());
+
+        // The deletion before running out still removed the components
+        assert_eq!(pos.get(&a.checked(&alloc, &merger).unwrap()), None);
+        assert_eq!(pos.get(&b.checked(&alloc, &merger).unwrap()), Some(&Pos(1)));
+    }
+}
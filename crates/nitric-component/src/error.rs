@@ -7,6 +7,8 @@
 
 use std::fmt::Debug;
 
+use crate::command::Applied;
+
 /// Error returned when the ID is invalid.
 #[derive(Debug, Error, Eq, PartialEq)]
 #[error(display = "ID {:?} is invalid", _0)]
@@ -17,3 +19,13 @@ pub struct InvalidIdError<I: Debug>(pub I);
 #[derive(Debug, Error, Eq, PartialEq)]
 #[error(display = "ran out of memory / resources")]
 pub struct OomError;
+
+/// Error returned by `CommandBuffer::apply` when an ID could not be created.
+///
+/// Carries the result of the commands applied before the failing creation.
+#[derive(Debug, Error)]
+#[error(display = "ran out of memory / resources while applying commands")]
+pub struct ApplyError<I: Debug> {
+    /// The commands applied before running out of IDs.
+    pub applied: Applied<I>,
+}
@@ -35,6 +35,13 @@
 //!
 //! * `impls`
 //!
+//! With the `lock` feature enabled, the `lock` module provides storages and
+//! allocators wrapped in `nitric-lock` locks.
+//!
+//! Structural changes (creating and deleting IDs, inserting and removing
+//! components) can be deferred with a `command::CommandBuffer`, which can be
+//! shared between threads.
+//!
 //! Additionally, error types can be found in `error`.
 //! Utility types can be found in `util`.
 //! A prelude for common traits & types can be imported using `use
@@ -45,11 +52,14 @@ extern crate err_derive;
 
 pub mod allocator;
 pub mod bit_set;
+pub mod command;
 pub mod id;
 pub mod storage;
 
 pub mod error;
 pub mod impls;
+#[cfg(feature = "lock")]
+pub mod lock;
 
 pub mod prelude;
 pub mod util;
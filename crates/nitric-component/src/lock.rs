@@ -0,0 +1,271 @@
+//! Integration with `nitric-lock`
+//!
+//! Provides `LockedStorage` and `LockedAllocator`, which wrap a storage or
+//! an allocator in a `nitric_lock::RwLock`. Both implement `ReadLock`,
+//! `WriteLock` and `UpgradableLock`, so they can be acquired together with
+//! any other nitric lock:
+//!
+//! ```
+//! use nitric_component::{
+//!     allocator::Create,
+//!     id::MergingDeletion,
+//!     impls::{FlatAllocator, FlatUsize},
+//!     lock::{LockedAllocator, LockedStorage},
+//! };
+//! use nitric_lock::{lock, LockGroup, ReadLock, WriteLock};
+//!
+//! let mut group = LockGroup::new();
+//! let mut token = group.token();
+//!
+//! let (alloc, merger) = FlatAllocator::new();
+//! let alloc = LockedAllocator::new(&mut group, alloc);
+//! let pos = LockedStorage::<FlatUsize, f32>::new(&mut group);
+//! let vel = LockedStorage::<FlatUsize, f32>::new(&mut group);
+//!
+//! let (mut alloc, mut pos, mut vel) =
+//!     lock!(&mut token, alloc.write(), pos.write(), vel.write()).unwrap();
+//!
+//! let id = alloc.create().unwrap();
+//! let checked = id.checked(&*alloc, &merger).unwrap();
+//! pos.insert(checked, 0.0);
+//! vel.insert(checked, 2.0);
+//! ```
+
+use std::fmt::{self, Debug, Formatter};
+
+use nitric_lock::{
+    LockGroup, LockId, LockInfo, ReadLock, RwLock, RwLockReadGuard, RwLockUpgradableReadGuard,
+    RwLockWriteGuard, UpgradableLock, WriteLock,
+};
+
+use crate::{id::SparseLinear, storage::Storage};
+
+/// A `Storage` wrapped in a `RwLock`.
+pub struct LockedStorage<ID, C>
+where
+    ID: SparseLinear,
+{
+    inner: RwLock<Storage<ID, C>>,
+}
+
+impl<ID, C> LockedStorage<ID, C>
+where
+    ID: SparseLinear,
+{
+    /// Creates a new, empty storage, allocating its lock from `group`.
+    pub fn new(group: &mut LockGroup) -> Self {
+        LockedStorage::from_storage(group, Storage::new())
+    }
+
+    /// Wraps an existing `storage`, allocating its lock from `group`.
+    pub fn from_storage(group: &mut LockGroup, storage: Storage<ID, C>) -> Self {
+        LockedStorage {
+            inner: group.rw_lock(storage),
+        }
+    }
+
+    /// Returns the ID of the lock.
+    pub fn lock_id(&self) -> LockId {
+        self.inner.lock_id()
+    }
+}
+
+impl<ID, C> Debug for LockedStorage<ID, C>
+where
+    ID: SparseLinear,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedStorage")
+            .field("lock_id", &self.lock_id())
+            .finish()
+    }
+}
+
+impl<'a, ID, C> ReadLock<'a> for &'a LockedStorage<ID, C>
+where
+    ID: SparseLinear + 'a,
+    C: 'a,
+{
+    type Output = RwLockReadGuard<'a, Storage<ID, C>>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        ReadLock::lock_info(&&self.inner)
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as ReadLock<'a>>::Output {
+        ReadLock::lock_unchecked(&self.inner)
+    }
+}
+
+impl<'a, ID, C> WriteLock<'a> for &'a LockedStorage<ID, C>
+where
+    ID: SparseLinear + 'a,
+    C: 'a,
+{
+    type Output = RwLockWriteGuard<'a, Storage<ID, C>>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        WriteLock::lock_info(&&self.inner)
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as WriteLock<'a>>::Output {
+        WriteLock::lock_unchecked(&self.inner)
+    }
+}
+
+impl<'a, ID, C> UpgradableLock<'a> for &'a LockedStorage<ID, C>
+where
+    ID: SparseLinear + 'a,
+    C: 'a,
+{
+    type Output = RwLockUpgradableReadGuard<'a, Storage<ID, C>>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        UpgradableLock::lock_info(&&self.inner)
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as UpgradableLock<'a>>::Output {
+        UpgradableLock::lock_unchecked(&self.inner)
+    }
+}
+
+/// An allocator wrapped in a `RwLock`.
+pub struct LockedAllocator<A> {
+    inner: RwLock<A>,
+}
+
+impl<A> LockedAllocator<A> {
+    /// Wraps `allocator`, allocating its lock from `group`.
+    pub fn new(group: &mut LockGroup, allocator: A) -> Self {
+        LockedAllocator {
+            inner: group.rw_lock(allocator),
+        }
+    }
+
+    /// Returns the ID of the lock.
+    pub fn lock_id(&self) -> LockId {
+        self.inner.lock_id()
+    }
+}
+
+impl<A> Debug for LockedAllocator<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedAllocator")
+            .field("lock_id", &self.lock_id())
+            .finish()
+    }
+}
+
+impl<'a, A: 'a> ReadLock<'a> for &'a LockedAllocator<A> {
+    type Output = RwLockReadGuard<'a, A>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        ReadLock::lock_info(&&self.inner)
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as ReadLock<'a>>::Output {
+        ReadLock::lock_unchecked(&self.inner)
+    }
+}
+
+impl<'a, A: 'a> WriteLock<'a> for &'a LockedAllocator<A> {
+    type Output = RwLockWriteGuard<'a, A>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        WriteLock::lock_info(&&self.inner)
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as WriteLock<'a>>::Output {
+        WriteLock::lock_unchecked(&self.inner)
+    }
+}
+
+impl<'a, A: 'a> UpgradableLock<'a> for &'a LockedAllocator<A> {
+    type Output = RwLockUpgradableReadGuard<'a, A>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        UpgradableLock::lock_info(&&self.inner)
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as UpgradableLock<'a>>::Output {
+        UpgradableLock::lock_unchecked(&self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use nitric_lock::lock;
+
+    use super::*;
+    use crate::{
+        allocator::Create,
+        id::MergingDeletion,
+        impls::{FlatAllocator, FlatUsize},
+    };
+
+    #[test]
+    fn system() {
+        let mut group = LockGroup::new();
+        let (alloc, merger) = FlatAllocator::new();
+        let alloc = LockedAllocator::new(&mut group, alloc);
+        let pos = LockedStorage::<FlatUsize, i32>::new(&mut group);
+        let vel = LockedStorage::<FlatUsize, i32>::new(&mut group);
+
+        let ids: Vec<FlatUsize> = {
+            let mut token = group.token();
+            let (mut alloc, mut pos, mut vel) =
+                lock!(&mut token, alloc.write(), pos.write(), vel.write()).unwrap();
+
+            (0..10)
+                .map(|i| {
+                    let id = alloc.create().unwrap();
+                    let checked = id.checked(&*alloc, &merger).unwrap();
+                    pos.insert(checked, i);
+                    vel.insert(checked, 2 * i);
+
+                    id
+                })
+                .collect()
+        };
+
+        let group = Arc::new(group);
+        let state = Arc::new((alloc, pos, vel, merger, ids));
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let group = group.clone();
+                let state = state.clone();
+
+                thread::spawn(move || {
+                    let (ref alloc, ref pos, ref vel, ref merger, ref ids) = *state;
+                    let mut token = group.token();
+
+                    let (vel, alloc, mut pos) =
+                        lock!(&mut token, vel.read(), alloc.read(), pos.write()).unwrap();
+
+                    for id in ids {
+                        let checked = id.checked(&*alloc, merger).unwrap();
+
+                        *pos.get_mut(&checked).unwrap() += *vel.get(&checked).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let (ref alloc, ref pos, _, ref merger, ref ids) = *state;
+        let mut token = group.token();
+        let (alloc, pos) = lock!(&mut token, alloc.read(), pos.read()).unwrap();
+
+        for (i, id) in ids.iter().enumerate() {
+            let checked = id.checked(&*alloc, merger).unwrap();
+
+            assert_eq!(*pos.get(&checked).unwrap(), 5 * i as i32);
+        }
+    }
+}
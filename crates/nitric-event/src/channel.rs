@@ -0,0 +1,358 @@
+//! The event channel and its readers.
+
+use std::{
+    collections::vec_deque::{self, VecDeque},
+    fmt::{self, Debug, Formatter},
+    iter::FusedIterator,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+/// The capacity of a channel created with `EventChannel::new`.
+const DEFAULT_CAPACITY: usize = 64;
+
+static NEXT_CHANNEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies a reader of an `EventChannel` and its position in it.
+///
+/// Returned by `EventChannel::register_reader`. A reader which is no longer
+/// used should be passed to `EventChannel::remove_reader`, because the
+/// channel keeps all events it has not read yet.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ReaderId {
+    channel: usize,
+    index: usize,
+}
+
+/// A channel for events of type `E`, read by any number of readers.
+///
+/// Events are stored in a ring buffer. Once it is full, the oldest event is
+/// overwritten if every reader has read it, otherwise the buffer grows, so
+/// slow readers never miss events.
+///
+/// Writing requires `&mut self`, while reading only needs `&self`, so
+/// several systems can read from the same channel in parallel.
+///
+/// ```
+/// use nitric_event::EventChannel;
+///
+/// let mut channel = EventChannel::new();
+/// let mut reader = channel.register_reader();
+///
+/// channel.write("collision");
+/// channel.extend(vec!["input", "message"]);
+///
+/// assert_eq!(
+///     channel.read(&mut reader).collect::<Vec<_>>(),
+///     [&"collision", &"input", &"message"]
+/// );
+/// assert_eq!(channel.read(&mut reader).len(), 0);
+/// ```
+pub struct EventChannel<E> {
+    events: VecDeque<E>,
+    /// The absolute position of the oldest stored event.
+    head: u64,
+    capacity: usize,
+    /// The absolute positions of the next event each reader will read.
+    readers: Vec<Option<AtomicU64>>,
+    id: usize,
+}
+
+impl<E> EventChannel<E> {
+    /// Creates an empty channel with a default capacity.
+    pub fn new() -> Self {
+        EventChannel::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates an empty channel which can hold `capacity` events before it
+    /// starts overwriting or growing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0, "An event channel needs a capacity");
+
+        EventChannel {
+            events: VecDeque::with_capacity(capacity),
+            head: 0,
+            capacity,
+            readers: Vec::new(),
+            id: NEXT_CHANNEL.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Registers a new reader, which will read all events written from now
+    /// on.
+    pub fn register_reader(&mut self) -> ReaderId {
+        let cursor = Some(AtomicU64::new(self.end()));
+
+        let index = match self.readers.iter().position(Option::is_none) {
+            Some(index) => {
+                self.readers[index] = cursor;
+
+                index
+            }
+            None => {
+                self.readers.push(cursor);
+
+                self.readers.len() - 1
+            }
+        };
+
+        ReaderId {
+            channel: self.id,
+            index,
+        }
+    }
+
+    /// Removes a reader, so events it has not read can be overwritten.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reader` belongs to a different channel.
+    pub fn remove_reader(&mut self, reader: ReaderId) {
+        self.check(&reader);
+
+        self.readers[reader.index] = None;
+    }
+
+    /// Checks if any readers are registered. Events written without readers
+    /// are dropped immediately.
+    pub fn has_readers(&self) -> bool {
+        self.readers.iter().any(Option::is_some)
+    }
+
+    /// Writes a single event.
+    pub fn write(&mut self, event: E) {
+        if !self.has_readers() {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            match self.oldest_cursor() > self.head {
+                true => {
+                    self.events.pop_front();
+                    self.head += 1;
+                }
+                false => {
+                    self.events.reserve(self.capacity);
+                    self.capacity *= 2;
+                }
+            }
+        }
+
+        self.events.push_back(event);
+    }
+
+    /// Reads all events `reader` has not read yet, in the order they were
+    /// written, and marks them as read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reader` belongs to a different channel.
+    pub fn read(&self, reader: &mut ReaderId) -> Events<'_, E> {
+        self.check(reader);
+
+        let cursor = self.cursor(reader).swap(self.end(), Ordering::Relaxed);
+
+        Events {
+            inner: self.events.range((cursor - self.head) as usize..),
+        }
+    }
+
+    /// Returns the number of events `reader` has not read yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `reader` belongs to a different channel.
+    pub fn unread(&self, reader: &ReaderId) -> usize {
+        self.check(reader);
+
+        (self.end() - self.cursor(reader).load(Ordering::Relaxed)) as usize
+    }
+
+    /// Returns the number of stored events, including ones that have been
+    /// read by all readers but not overwritten yet.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Checks if no events are stored.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Returns the number of events the channel can hold before it has to
+    /// overwrite or grow.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The absolute position of the next event to be written.
+    fn end(&self) -> u64 {
+        self.head + self.events.len() as u64
+    }
+
+    /// The position of the reader which is furthest behind.
+    fn oldest_cursor(&mut self) -> u64 {
+        let end = self.end();
+
+        self.readers
+            .iter_mut()
+            .flatten()
+            .map(|cursor| *cursor.get_mut())
+            .min()
+            .unwrap_or(end)
+    }
+
+    fn cursor(&self, reader: &ReaderId) -> &AtomicU64 {
+        self.readers[reader.index]
+            .as_ref()
+            .expect("Reader has been removed")
+    }
+
+    fn check(&self, reader: &ReaderId) {
+        assert_eq!(
+            reader.channel, self.id,
+            "Reader belongs to a different channel"
+        );
+    }
+}
+
+impl<E> Default for EventChannel<E> {
+    fn default() -> Self {
+        EventChannel::new()
+    }
+}
+
+impl<E> Extend<E> for EventChannel<E> {
+    fn extend<I: IntoIterator<Item = E>>(&mut self, events: I) {
+        for event in events {
+            self.write(event);
+        }
+    }
+}
+
+impl<E> Debug for EventChannel<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventChannel")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .field("readers", &self.readers.iter().flatten().count())
+            .finish()
+    }
+}
+
+/// Iterator over the events read with `EventChannel::read`.
+#[derive(Clone, Debug)]
+pub struct Events<'a, E> {
+    inner: vec_deque::Iter<'a, E>,
+}
+
+impl<'a, E> Iterator for Events<'a, E> {
+    type Item = &'a E;
+
+    fn next(&mut self) -> Option<&'a E> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<E> DoubleEndedIterator for Events<'_, E> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<E> ExactSizeIterator for Events<'_, E> {}
+
+impl<E> FusedIterator for Events<'_, E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(channel: &EventChannel<u32>, reader: &mut ReaderId) -> Vec<u32> {
+        channel.read(reader).cloned().collect()
+    }
+
+    #[test]
+    fn independent_readers() {
+        let mut channel = EventChannel::new();
+        channel.write(0);
+
+        let mut a = channel.register_reader();
+        channel.extend(1..3);
+        let mut b = channel.register_reader();
+        channel.write(3);
+
+        assert_eq!(channel.unread(&a), 3);
+        assert_eq!(read(&channel, &mut a), [1, 2, 3]);
+        assert_eq!(read(&channel, &mut a), []);
+        assert_eq!(read(&channel, &mut b), [3]);
+
+        channel.write(4);
+        assert_eq!(read(&channel, &mut b), [4]);
+        assert_eq!(read(&channel, &mut a), [4]);
+    }
+
+    #[test]
+    fn overwrite_and_grow() {
+        let mut channel = EventChannel::with_capacity(4);
+        let mut fast = channel.register_reader();
+        let mut slow = channel.register_reader();
+
+        for i in 0..4 {
+            channel.write(i);
+            assert_eq!(read(&channel, &mut fast), [i]);
+        }
+        assert_eq!(read(&channel, &mut slow), [0, 1, 2, 3]);
+
+        // Everything was read, so old events are overwritten
+        channel.extend(4..8);
+        assert_eq!(channel.capacity(), 4);
+        assert_eq!(read(&channel, &mut fast), [4, 5, 6, 7]);
+
+        // `slow` lags behind, so the buffer has to grow
+        channel.extend(8..11);
+        assert_eq!(channel.capacity(), 8);
+        assert_eq!(read(&channel, &mut slow), (4..11).collect::<Vec<_>>());
+
+        channel.remove_reader(slow);
+        channel.extend(11..30);
+        assert_eq!(channel.capacity(), 32);
+        assert_eq!(read(&channel, &mut fast), (8..30).collect::<Vec<_>>());
+
+        // The slot of the removed reader is reused
+        let mut late = channel.register_reader();
+        channel.write(30);
+        assert_eq!(read(&channel, &mut late), [30]);
+    }
+
+    #[test]
+    fn no_readers() {
+        let mut channel = EventChannel::new();
+        channel.write(1);
+        assert!(channel.is_empty());
+
+        let reader = channel.register_reader();
+        channel.write(2);
+        assert_eq!(channel.len(), 1);
+
+        channel.remove_reader(reader);
+        assert!(!channel.has_readers());
+    }
+
+    #[test]
+    #[should_panic(expected = "Reader belongs to a different channel")]
+    fn wrong_channel() {
+        let mut a = EventChannel::<u32>::new();
+        let b = EventChannel::<u32>::new();
+
+        let mut reader = a.register_reader();
+        b.read(&mut reader);
+    }
+}
@@ -0,0 +1,19 @@
+#![warn(missing_docs)]
+#![deny(unused_must_use)]
+
+//! # `nitric-event`
+//!
+//! Event channels, which allow systems to communicate without knowing about
+//! each other. An `EventChannel` stores events in a ring buffer, and every
+//! reader tracks its own position with a `ReaderId`.
+//!
+//! ## Features
+//!
+//! * `world`: Adds the `world` module, with convenience methods for channels
+//!   stored in a `nitric_world::World`.
+
+pub use self::channel::{EventChannel, Events, ReaderId};
+
+mod channel;
+#[cfg(feature = "world")]
+pub mod world;
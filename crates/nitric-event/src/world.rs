@@ -0,0 +1,127 @@
+//! Integration with `nitric-world`
+//!
+//! An `EventChannel` is a regular resource, so it can be stored in a `World`
+//! and declared in a `nitric_graph::Access` like any other resource. Since
+//! reading only borrows the channel immutably, readers can run in parallel;
+//! writers need mutable access.
+//!
+//! ```
+//! use nitric_event::{world::WorldEvents, EventChannel};
+//! use nitric_world::World;
+//!
+//! let mut world = World::new();
+//! world.insert("collisions", EventChannel::<(u32, u32)>::new());
+//!
+//! let mut reader = world.register_reader::<(u32, u32), _>("collisions");
+//! world.write_event("collisions", (1u32, 2u32));
+//!
+//! let channel = world.fetch::<EventChannel<(u32, u32)>, _>("collisions");
+//! assert_eq!(channel.read(&mut reader).collect::<Vec<_>>(), [&(1, 2)]);
+//! ```
+
+use std::{borrow::Borrow, hash::Hash};
+
+use nitric_world::World;
+
+use crate::{EventChannel, ReaderId};
+
+/// Convenience methods for event channels stored in a `World`.
+///
+/// All methods fetch the channel stored under the given key, and panic if it
+/// is missing or borrowed, like `World::fetch_mut`.
+pub trait WorldEvents<K> {
+    /// Registers a reader for the channel of `E` stored under `k`.
+    fn register_reader<E, Q>(&self, k: &Q) -> ReaderId
+    where
+        E: Send + Sync + 'static,
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+
+    /// Writes `event` to the channel of `E` stored under `k`.
+    fn write_event<E, Q>(&self, k: &Q, event: E)
+    where
+        E: Send + Sync + 'static,
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq;
+}
+
+impl<K> WorldEvents<K> for World<K>
+where
+    K: Hash + Eq,
+{
+    fn register_reader<E, Q>(&self, k: &Q) -> ReaderId
+    where
+        E: Send + Sync + 'static,
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.fetch_mut::<EventChannel<E>, Q>(k).register_reader()
+    }
+
+    fn write_event<E, Q>(&self, k: &Q, event: E)
+    where
+        E: Send + Sync + 'static,
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.fetch_mut::<EventChannel<E>, Q>(k).write(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use nitric_graph::{Access, GraphBuilder, ResourceId};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Collision(u32);
+
+    fn channel() -> ResourceId {
+        ResourceId::of::<EventChannel<Collision>, _>("collisions")
+    }
+
+    #[test]
+    fn graph() {
+        let mut world = World::new();
+        world.insert("collisions", EventChannel::<Collision>::new());
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let mut builder = GraphBuilder::new().with_threads(2);
+
+        let mut frame = 0;
+        builder.add(
+            Access::new().write(channel()),
+            move |world: &World<&str>| {
+                frame += 1;
+                world.write_event("collisions", Collision(frame));
+            },
+        );
+
+        for _ in 0..2 {
+            let mut reader = world.register_reader::<Collision, _>("collisions");
+            let received = received.clone();
+
+            builder.add(Access::new().read(channel()), move |world: &World<&str>| {
+                let channel = world.fetch::<EventChannel<Collision>, _>("collisions");
+                received
+                    .lock()
+                    .unwrap()
+                    .extend(channel.read(&mut reader).map(|c| c.0));
+            });
+        }
+
+        let mut graph = builder.build();
+        assert_eq!(graph.stages().len(), 2);
+
+        for _ in 0..3 {
+            graph.run(&world);
+        }
+
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        assert_eq!(received, [1, 1, 2, 2, 3, 3]);
+    }
+}
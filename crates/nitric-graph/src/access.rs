@@ -0,0 +1,134 @@
+//! Declaring the resources a system accesses.
+
+use std::{any::TypeId, hash::Hash};
+
+use nitric_lock::LockId;
+use nitric_world::{key_hash, Resource};
+
+/// Identifies a resource a system accesses.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ResourceId {
+    /// A resource stored in a `nitric_world::World`, identified by its type
+    /// and a hash of its key.
+    World {
+        /// The type of the resource.
+        type_id: TypeId,
+        /// The hash of the key of the resource, see `nitric_world::key_hash`.
+        key: u64,
+    },
+    /// A lock of `nitric-lock`, e.g. a `Mutex` or a `RwLock`.
+    Lock(LockId),
+}
+
+impl ResourceId {
+    /// Returns the ID of the resource of type `T` stored under `key` in a
+    /// `World`.
+    ///
+    /// Keys are only compared by their hash, so a hash collision can at worst
+    /// make two systems conflict which would not need to.
+    pub fn of<T: Resource, Q>(key: &Q) -> Self
+    where
+        Q: ?Sized + Hash,
+    {
+        ResourceId::World {
+            type_id: TypeId::of::<T>(),
+            key: key_hash(key),
+        }
+    }
+}
+
+impl From<LockId> for ResourceId {
+    fn from(id: LockId) -> Self {
+        ResourceId::Lock(id)
+    }
+}
+
+/// The set of resources a system reads and writes.
+///
+/// ```
+/// use nitric_graph::{Access, ResourceId};
+///
+/// let physics = Access::new()
+///     .read(ResourceId::of::<f32, _>("dt"))
+///     .write(ResourceId::of::<Vec<f32>, _>("pos"));
+/// let render = Access::new().read(ResourceId::of::<Vec<f32>, _>("pos"));
+///
+/// assert!(physics.conflicts_with(&render));
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Access {
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+}
+
+impl Access {
+    /// Creates an empty access set, which conflicts with nothing.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a resource which is read.
+    pub fn read(mut self, id: impl Into<ResourceId>) -> Self {
+        self.reads.push(id.into());
+
+        self
+    }
+
+    /// Adds a resource which is written.
+    pub fn write(mut self, id: impl Into<ResourceId>) -> Self {
+        self.writes.push(id.into());
+
+        self
+    }
+
+    /// Returns the resources which are read.
+    pub fn reads(&self) -> &[ResourceId] {
+        &self.reads
+    }
+
+    /// Returns the resources which are written.
+    pub fn writes(&self) -> &[ResourceId] {
+        &self.writes
+    }
+
+    /// Checks if systems with these two access sets cannot run in parallel,
+    /// which is the case if one of them writes a resource the other one
+    /// accesses.
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        self.writes
+            .iter()
+            .any(|id| other.reads.contains(id) || other.writes.contains(id))
+            || other.writes.iter().any(|id| self.reads.contains(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nitric_lock::LockGroup;
+
+    use super::*;
+
+    #[test]
+    fn conflicts() {
+        let mut group = LockGroup::new();
+        let lock = group.mutex(());
+
+        let a = ResourceId::of::<u32, _>("a");
+        let b = ResourceId::of::<u32, _>("b");
+        assert_eq!(a, ResourceId::of::<u32, _>(&"a".to_owned()));
+        assert_ne!(a, b);
+        assert_ne!(a, ResourceId::of::<u64, _>("a"));
+
+        let read_a = Access::new().read(a);
+        let write_a = Access::new().write(a);
+        let write_b = Access::new().write(b).write(lock.lock_id());
+
+        assert!(!read_a.conflicts_with(&read_a));
+        assert!(read_a.conflicts_with(&write_a));
+        assert!(write_a.conflicts_with(&read_a));
+        assert!(write_a.conflicts_with(&write_a));
+        assert!(!write_a.conflicts_with(&write_b));
+        assert!(write_b.conflicts_with(&Access::new().read(lock.lock_id())));
+        assert!(!Access::new().conflicts_with(&write_b));
+    }
+}
@@ -0,0 +1,665 @@
+//! Systems which may take several frames to complete.
+
+use std::{
+    any::TypeId,
+    hash::Hash,
+    ptr,
+    sync::Arc,
+    task::{Context, Poll, RawWaker, RawWakerVTable, Wake, Waker},
+    thread::{self, Thread},
+};
+
+use nitric_world::{Claim, World};
+
+use crate::{Access, ResourceId};
+
+/// A system whose runs may span several frames, i.e. several calls of
+/// `Graph::run`. Added with `GraphBuilder::add_async`.
+///
+/// This is a minimal poll-based interface which does not depend on any
+/// async framework; see `FutureSystem` (feature `futures`) for running a
+/// `Future`.
+///
+/// # Exclusive access
+///
+/// The resources declared in the system's `Access` are claimed in the
+/// context for the whole run (see `Claimable`), so `start` and `poll` can
+/// fetch them, while any other code fails as if they were borrowed. If a
+/// resource is still borrowed by other code when a run is about to start,
+/// the start is postponed to the next frame.
+///
+/// The graph skips every system conflicting with a run in progress when it
+/// reaches that system's stage. Skipped systems are not notified; use
+/// `Graph::skipped` to find out which systems did not run in a frame.
+pub trait AsyncSystem<C: ?Sized>: Send {
+    /// Starts a new run. Called at the system's stage whenever the previous
+    /// run has completed, followed by a call to `poll`.
+    fn start(&mut self, context: &C);
+
+    /// Advances the current run, returning `Poll::Ready` once it completes.
+    ///
+    /// Called at the system's stage in every frame until the run completes.
+    /// Like `Future::poll`, returning `Poll::Pending` requires waking the
+    /// waker of `cx` once the run can make progress. When the run has to be
+    /// joined (see `Completion::JoinAfter`), the graph blocks until then.
+    fn poll(&mut self, context: &C, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+/// A context whose resources can be claimed for a whole run of an
+/// `AsyncSystem`, which outlives the reference to the context.
+///
+/// A `World` claims the `ResourceId::World` resources of an `Access`, but
+/// not the `ResourceId::Lock`s. The unit type claims nothing.
+pub trait Claimable {
+    /// Keeps the claimed resources reserved until passed to `release`.
+    type Claim: Send + 'static;
+
+    /// Claims the resources declared in `access`, or returns `None` if some
+    /// of them cannot be claimed because other code borrows them.
+    fn claim(&self, access: &Access) -> Option<Self::Claim>;
+
+    /// Runs `f`, allowing it to access the resources of `claim` from the
+    /// current thread.
+    fn enter<R>(&self, claim: &Self::Claim, f: impl FnOnce() -> R) -> R;
+
+    /// Releases the resources of `claim`.
+    fn release(&self, claim: Self::Claim);
+}
+
+impl Claimable for () {
+    type Claim = ();
+
+    fn claim(&self, _: &Access) -> Option<()> {
+        Some(())
+    }
+
+    fn enter<R>(&self, _: &(), f: impl FnOnce() -> R) -> R {
+        f()
+    }
+
+    fn release(&self, _: ()) {}
+}
+
+impl<K: Hash + Eq> Claimable for World<K> {
+    type Claim = Claim;
+
+    fn claim(&self, access: &Access) -> Option<Claim> {
+        fn world_ids(ids: &[ResourceId]) -> Vec<(TypeId, u64)> {
+            ids.iter()
+                .filter_map(|id| match *id {
+                    ResourceId::World { type_id, key } => Some((type_id, key)),
+                    ResourceId::Lock(_) => None,
+                })
+                .collect()
+        }
+
+        World::claim(
+            self,
+            &world_ids(access.reads()),
+            &world_ids(access.writes()),
+        )
+        .ok()
+    }
+
+    fn enter<R>(&self, claim: &Claim, f: impl FnOnce() -> R) -> R {
+        claim.enter(f)
+    }
+
+    fn release(&self, claim: Claim) {
+        World::release(self, claim);
+    }
+}
+
+/// Specifies when a run of an `AsyncSystem` has to complete.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Completion {
+    /// The run has to complete `n` frames after the one it started in; the
+    /// graph blocks in that frame until it does. `JoinAfter(0)` completes
+    /// every run in the frame it started.
+    JoinAfter(u64),
+    /// The run completes whenever it is ready; it is polled once per frame.
+    WhenReady,
+}
+
+/// The claim of the current run of an `AsyncNode`, which erases the
+/// `Claimable` bound, so only `GraphBuilder::add_async` requires it.
+trait RunClaim<C: ?Sized>: Send {
+    fn claim(&mut self, context: &C, access: &Access) -> bool;
+
+    fn enter(&self, context: &C, f: &mut dyn FnMut());
+
+    fn release(&mut self, context: &C);
+}
+
+struct Claimed<T>(Option<T>);
+
+impl<C, T> RunClaim<C> for Claimed<T>
+where
+    C: Claimable<Claim = T> + ?Sized,
+    T: Send,
+{
+    fn claim(&mut self, context: &C, access: &Access) -> bool {
+        self.0 = context.claim(access);
+
+        self.0.is_some()
+    }
+
+    fn enter(&self, context: &C, f: &mut dyn FnMut()) {
+        let claim = self.0.as_ref().expect("Run was not started");
+
+        context.enter(claim, f)
+    }
+
+    fn release(&mut self, context: &C) {
+        if let Some(claim) = self.0.take() {
+            context.release(claim);
+        }
+    }
+}
+
+/// An `AsyncSystem` together with the state of its current run.
+pub(crate) struct AsyncNode<C: ?Sized> {
+    system: Box<dyn AsyncSystem<C>>,
+    completion: Completion,
+    claim: Box<dyn RunClaim<C>>,
+    /// The frame the current run started in, if there is one.
+    started: Option<u64>,
+}
+
+impl<C: ?Sized> AsyncNode<C> {
+    pub fn new(system: Box<dyn AsyncSystem<C>>, completion: Completion) -> Self
+    where
+        C: Claimable,
+    {
+        AsyncNode {
+            system,
+            completion,
+            claim: Box::new(Claimed::<C::Claim>(None)),
+            started: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn run(&mut self, context: &C, access: &Access, frame: u64) {
+        // Ends the run if the system panics, so the next frame starts over
+        let node = EndOnPanic {
+            node: self,
+            context,
+        };
+        let AsyncNode {
+            ref mut system,
+            ref mut claim,
+            ref mut started,
+            completion,
+        } = *node.node;
+
+        let started = match *started {
+            Some(started) => started,
+            // Postponed while other code borrows the resources
+            None if !claim.claim(context, access) => return,
+            None => {
+                *started = Some(frame);
+                claim.enter(context, &mut || system.start(context));
+
+                frame
+            }
+        };
+
+        let join = match completion {
+            Completion::JoinAfter(n) => frame - started >= n,
+            Completion::WhenReady => false,
+        };
+
+        // Polled again in the next frame anyway, unless the run is joined
+        let waker = match join {
+            true => Waker::from(Arc::new(ThreadWaker(thread::current()))),
+            false => noop_waker(),
+        };
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            let mut poll = Poll::Pending;
+            claim.enter(context, &mut || poll = system.poll(context, &mut cx));
+
+            match (poll, join) {
+                (Poll::Ready(()), _) => break,
+                (Poll::Pending, true) => thread::park(),
+                (Poll::Pending, false) => return,
+            }
+        }
+
+        node.node.end(context);
+    }
+
+    /// Ends the current run, releasing its claim.
+    fn end(&mut self, context: &C) {
+        self.started = None;
+        self.claim.release(context);
+    }
+}
+
+/// Ends the run of `node` when dropped during a panic.
+struct EndOnPanic<'a, C: ?Sized> {
+    node: &'a mut AsyncNode<C>,
+    context: &'a C,
+}
+
+impl<C: ?Sized> Drop for EndOnPanic<'_, C> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.node.end(self.context);
+        }
+    }
+}
+
+/// Unparks the thread joining a run.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn noop_waker() -> Waker {
+    fn clone(_: *const ()) -> RawWaker {
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+
+    fn noop(_: *const ()) {}
+
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+    // Safe because the vtable ignores the data pointer
+    unsafe { Waker::from_raw(RawWaker::new(ptr::null(), &VTABLE)) }
+}
+
+#[cfg(feature = "futures")]
+pub use self::future::FutureSystem;
+
+#[cfg(feature = "futures")]
+mod future {
+    use std::{
+        fmt::{self, Debug, Formatter},
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use super::AsyncSystem;
+
+    /// Adapts a function creating a `Future` for every run to an
+    /// `AsyncSystem`.
+    ///
+    /// The future is polled with the waker passed by the graph, which only
+    /// has an effect while the run is joined; otherwise, the future is polled
+    /// once per frame regardless.
+    pub struct FutureSystem<F, Fut> {
+        spawn: F,
+        future: Option<Pin<Box<Fut>>>,
+    }
+
+    impl<F, Fut> FutureSystem<F, Fut> {
+        /// Creates an adapter calling `spawn` at the start of every run.
+        pub fn new(spawn: F) -> Self {
+            FutureSystem {
+                spawn,
+                future: None,
+            }
+        }
+    }
+
+    impl<C, F, Fut> AsyncSystem<C> for FutureSystem<F, Fut>
+    where
+        C: ?Sized,
+        F: FnMut(&C) -> Fut + Send,
+        Fut: Future<Output = ()> + Send,
+    {
+        fn start(&mut self, context: &C) {
+            self.future = Some(Box::pin((self.spawn)(context)));
+        }
+
+        fn poll(&mut self, _: &C, cx: &mut Context<'_>) -> Poll<()> {
+            let future = self.future.as_mut().expect("Run was not started");
+
+            let poll = future.as_mut().poll(cx);
+            if poll.is_ready() {
+                self.future = None;
+            }
+
+            poll
+        }
+    }
+
+    impl<F, Fut> Debug for FutureSystem<F, Fut> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            f.debug_struct("FutureSystem")
+                .field("running", &self.future.is_some())
+                .finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{Access, GraphBuilder};
+
+    /// Completes every run after being polled `polls` times, waking the waker
+    /// right away when pending.
+    struct Countdown {
+        polls: usize,
+        remaining: usize,
+        runs: Arc<AtomicUsize>,
+    }
+
+    impl AsyncSystem<()> for Countdown {
+        fn start(&mut self, _: &()) {
+            self.remaining = self.polls;
+            self.runs.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn poll(&mut self, _: &(), cx: &mut Context<'_>) -> Poll<()> {
+            self.remaining -= 1;
+
+            match self.remaining {
+                0 => Poll::Ready(()),
+                _ => {
+                    cx.waker().wake_by_ref();
+
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    /// Completes every run once a thread spawned by `start` is done.
+    #[derive(Default)]
+    struct Background {
+        done: Arc<AtomicBool>,
+        waker: Arc<Mutex<Option<Waker>>>,
+    }
+
+    impl AsyncSystem<()> for Background {
+        fn start(&mut self, _: &()) {
+            self.done.store(false, Ordering::SeqCst);
+
+            let done = self.done.clone();
+            let waker = self.waker.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                done.store(true, Ordering::SeqCst);
+
+                if let Some(waker) = waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            });
+        }
+
+        fn poll(&mut self, _: &(), cx: &mut Context<'_>) -> Poll<()> {
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+
+            match self.done.load(Ordering::SeqCst) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            }
+        }
+    }
+
+    fn counter(counter: &Arc<AtomicUsize>) -> impl FnMut(&()) + Send + 'static {
+        let counter = counter.clone();
+
+        move |_: &()| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn id(key: &str) -> ResourceId {
+        ResourceId::of::<u32, _>(key)
+    }
+
+    #[test]
+    fn when_ready() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let conflicting = Arc::new(AtomicUsize::new(0));
+        let independent = Arc::new(AtomicUsize::new(0));
+
+        let mut builder = GraphBuilder::new().single_threaded();
+        let countdown = builder.add_async(
+            Access::new().write(id("a")),
+            Countdown {
+                polls: 3,
+                remaining: 0,
+                runs: runs.clone(),
+            },
+            Completion::WhenReady,
+        );
+        let reader = builder.add(Access::new().read(id("a")), counter(&conflicting));
+        builder.add(Access::new().read(id("b")), counter(&independent));
+        let mut graph = builder.build();
+
+        for _ in 0..2 {
+            graph.run(&());
+            assert!(graph.is_running(countdown));
+            assert_eq!(graph.skipped(), [reader]);
+        }
+        assert_eq!(conflicting.load(Ordering::Relaxed), 0);
+
+        graph.run(&());
+        assert!(!graph.is_running(countdown));
+        assert_eq!(graph.skipped(), []);
+        assert_eq!(conflicting.load(Ordering::Relaxed), 1);
+
+        graph.run(&());
+        assert!(graph.is_running(countdown));
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+        assert_eq!(conflicting.load(Ordering::Relaxed), 1);
+        assert_eq!(independent.load(Ordering::Relaxed), 4);
+        assert_eq!(graph.frame(), 4);
+    }
+
+    #[test]
+    fn join_after() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let conflicting = Arc::new(AtomicUsize::new(0));
+
+        let mut builder = GraphBuilder::new().with_threads(2);
+        let countdown = builder.add_async(
+            Access::new().write(id("a")),
+            Countdown {
+                polls: 5,
+                remaining: 0,
+                runs: runs.clone(),
+            },
+            Completion::JoinAfter(1),
+        );
+        builder.add(Access::new().read(id("a")), counter(&conflicting));
+        let mut graph = builder.build();
+
+        graph.run(&());
+        assert!(graph.is_running(countdown));
+        assert_eq!(conflicting.load(Ordering::Relaxed), 0);
+
+        graph.run(&());
+        assert!(!graph.is_running(countdown));
+        assert_eq!(conflicting.load(Ordering::Relaxed), 1);
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+    }
+
+    /// Adds one to the resource `"a"` per poll, completing after two polls.
+    /// Panics in the first poll if `panic` is set.
+    #[derive(Default)]
+    struct Increment {
+        polls: usize,
+        panic: bool,
+    }
+
+    impl AsyncSystem<World<&'static str>> for Increment {
+        fn start(&mut self, world: &World<&'static str>) {
+            self.polls = 0;
+            *world.fetch_mut::<u32, _>("a") = 0;
+        }
+
+        fn poll(&mut self, world: &World<&'static str>, _: &mut Context<'_>) -> Poll<()> {
+            if self.panic {
+                self.panic = false;
+                panic!("Failed while running");
+            }
+
+            *world.fetch_mut::<u32, _>("a") += 1;
+            self.polls += 1;
+
+            match self.polls {
+                2 => Poll::Ready(()),
+                _ => Poll::Pending,
+            }
+        }
+    }
+
+    fn world() -> World<&'static str> {
+        let mut world = World::new();
+        world.insert("a", 5u32);
+        world.insert("b", 0u32);
+
+        world
+    }
+
+    #[test]
+    fn claims_resources() {
+        let world = world();
+        let mut builder = GraphBuilder::new().single_threaded();
+        let increment = builder.add_async(
+            Access::new().write(id("a")),
+            Increment::default(),
+            Completion::WhenReady,
+        );
+        let mut graph = builder.build();
+
+        graph.run(&world);
+        assert!(graph.is_running(increment));
+        assert!(world.try_fetch::<u32, _>("a").is_err());
+        assert!(world.try_fetch_mut::<u32, _>("b").is_ok());
+
+        graph.run(&world);
+        assert!(!graph.is_running(increment));
+        assert_eq!(*world.fetch_mut::<u32, _>("a"), 2);
+    }
+
+    #[test]
+    fn borrowed_postpones() {
+        let world = world();
+        let mut builder = GraphBuilder::new().single_threaded();
+        let increment = builder.add_async(
+            Access::new().read(id("b")).write(id("a")),
+            Increment::default(),
+            Completion::WhenReady,
+        );
+        let mut graph = builder.build();
+
+        {
+            let _a = world.fetch::<u32, _>("a");
+            graph.run(&world);
+            assert!(!graph.is_running(increment));
+        }
+        assert_eq!(*world.fetch::<u32, _>("a"), 5);
+
+        graph.run(&world);
+        assert!(graph.is_running(increment));
+        assert!(world.try_fetch_mut::<u32, _>("b").is_err());
+    }
+
+    #[test]
+    fn panic_ends_run() {
+        let world = world();
+        let mut builder = GraphBuilder::new().single_threaded();
+        let increment = builder.add_async(
+            Access::new().write(id("a")),
+            Increment {
+                polls: 0,
+                panic: true,
+            },
+            Completion::WhenReady,
+        );
+        let mut graph = builder.build();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| graph.run(&world)));
+        assert!(result.is_err());
+        assert!(!graph.is_running(increment));
+        assert_eq!(*world.fetch_mut::<u32, _>("a"), 0);
+
+        // The next frame starts a new run
+        graph.run(&world);
+        assert!(graph.is_running(increment));
+        graph.run(&world);
+        assert_eq!(*world.fetch::<u32, _>("a"), 2);
+    }
+
+    #[test]
+    fn join_parks() {
+        let mut builder = GraphBuilder::new().with_threads(1);
+        let background = builder.add_async(
+            Access::new().write(id("a")),
+            Background::default(),
+            Completion::JoinAfter(0),
+        );
+        let mut graph = builder.build();
+
+        for _ in 0..2 {
+            graph.run(&());
+            assert!(!graph.is_running(background));
+        }
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn future() {
+        use std::{future::Future, pin::Pin};
+
+        struct YieldOnce(bool);
+
+        impl Future for YieldOnce {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                match self.0 {
+                    true => Poll::Ready(()),
+                    false => {
+                        self.0 = true;
+                        cx.waker().wake_by_ref();
+
+                        Poll::Pending
+                    }
+                }
+            }
+        }
+
+        let mut builder = GraphBuilder::new().single_threaded();
+        let id = builder.add_async(
+            Access::new(),
+            FutureSystem::new(|_: &()| YieldOnce(false)),
+            Completion::WhenReady,
+        );
+        let mut graph = builder.build();
+
+        graph.run(&());
+        assert!(graph.is_running(id));
+        graph.run(&());
+        assert!(!graph.is_running(id));
+    }
+}
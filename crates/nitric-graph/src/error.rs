@@ -0,0 +1,53 @@
+//! Error types of this crate.
+
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+use crate::{Label, SystemId};
+
+/// Error returned by `GraphBuilder::try_build` if the ordering constraints
+/// cannot be satisfied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GraphError {
+    /// A system is ordered relative to a label no system carries.
+    MissingDependency {
+        /// The system declaring the constraint.
+        system: SystemId,
+        /// The label it refers to.
+        label: Label,
+    },
+    /// The ordering constraints form a cycle.
+    Cycle {
+        /// The systems on the cycle, each one required to run before the
+        /// next. The first system is repeated at the end.
+        path: Vec<SystemId>,
+    },
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            GraphError::MissingDependency { system, ref label } => write!(
+                f,
+                "System #{} depends on `{:?}`, which no system is labeled with",
+                system.index(),
+                label
+            ),
+            GraphError::Cycle { ref path } => {
+                write!(f, "Systems depend on each other: ")?;
+                for (i, system) in path.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "#{}", system.index())?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for GraphError {}
@@ -0,0 +1,786 @@
+//! Building and running system graphs.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+use crate::{
+    async_system::AsyncNode, Access, AsyncSystem, Claimable, Completion, GraphError, Label, System,
+    SystemLabel, ThreadPool,
+};
+
+/// Identifies a system within a `Graph`; returned by `GraphBuilder::add`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SystemId(usize);
+
+impl SystemId {
+    /// Returns the index of the system, which is the order in which it was
+    /// added.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// What to do if a system is ordered relative to a label no system carries.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Missing {
+    /// Fail with `GraphError::MissingDependency`. This is the default.
+    #[default]
+    Error,
+    /// Drop the constraint, e.g. for systems which are only ordered relative
+    /// to optional plugins.
+    Ignore,
+}
+
+struct Node<C: ?Sized> {
+    access: Access,
+    kind: Kind<C>,
+    labels: Vec<Label>,
+    before: Vec<Label>,
+    after: Vec<Label>,
+}
+
+impl<C: ?Sized> Node<C> {
+    fn run(&mut self, context: &C, frame: u64) {
+        match self.kind {
+            Kind::Sync(ref mut system) => system.run(context),
+            Kind::Async(ref mut node) => node.run(context, &self.access, frame),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        match self.kind {
+            Kind::Sync(_) => false,
+            Kind::Async(ref node) => node.is_running(),
+        }
+    }
+}
+
+enum Kind<C: ?Sized> {
+    Sync(Box<dyn System<C>>),
+    Async(AsyncNode<C>),
+}
+
+enum Executor {
+    Pool(Arc<ThreadPool>),
+    Sequential,
+}
+
+/// Collects systems together with their accesses and builds a `Graph`.
+pub struct GraphBuilder<C: ?Sized> {
+    nodes: Vec<Node<C>>,
+    executor: Option<Executor>,
+    missing: Missing,
+}
+
+impl<C: ?Sized> GraphBuilder<C> {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        GraphBuilder {
+            nodes: Vec::new(),
+            executor: None,
+            missing: Missing::Error,
+        }
+    }
+
+    /// Adds a system which accesses the resources declared in `access`.
+    ///
+    /// If the system conflicts with systems added before it, it will run
+    /// after them, unless ordered otherwise with `before` or `after`.
+    pub fn add<S>(&mut self, access: Access, system: S) -> SystemId
+    where
+        S: System<C> + 'static,
+    {
+        self.push(access, Kind::Sync(Box::new(system)))
+    }
+
+    /// Adds an asynchronous system, whose runs complete as specified by
+    /// `completion`.
+    ///
+    /// While a run is in progress, the resources declared in `access` are
+    /// claimed in the context and systems conflicting with `access` are
+    /// skipped; see `AsyncSystem`.
+    pub fn add_async<S>(&mut self, access: Access, system: S, completion: Completion) -> SystemId
+    where
+        C: Claimable,
+        S: AsyncSystem<C> + 'static,
+    {
+        self.push(
+            access,
+            Kind::Async(AsyncNode::new(Box::new(system), completion)),
+        )
+    }
+
+    /// Builder-style version of `add`.
+    pub fn with<S>(mut self, access: Access, system: S) -> Self
+    where
+        S: System<C> + 'static,
+    {
+        self.add(access, system);
+
+        self
+    }
+
+    /// Builder-style version of `add_async`.
+    pub fn with_async<S>(mut self, access: Access, system: S, completion: Completion) -> Self
+    where
+        C: Claimable,
+        S: AsyncSystem<C> + 'static,
+    {
+        self.add_async(access, system, completion);
+
+        self
+    }
+
+    /// Labels the system `id`, so other systems can be ordered relative to
+    /// it. A label may be shared by several systems.
+    pub fn label(&mut self, id: SystemId, label: impl SystemLabel) -> &mut Self {
+        self.nodes[id.0].labels.push(label.into());
+
+        self
+    }
+
+    /// Requires the system `id` to run before all systems labeled `label`.
+    ///
+    /// The labeled systems do not need to be added yet.
+    pub fn before(&mut self, id: SystemId, label: impl SystemLabel) -> &mut Self {
+        self.nodes[id.0].before.push(label.into());
+
+        self
+    }
+
+    /// Requires the system `id` to run after all systems labeled `label`.
+    ///
+    /// The labeled systems do not need to be added yet.
+    pub fn after(&mut self, id: SystemId, label: impl SystemLabel) -> &mut Self {
+        self.nodes[id.0].after.push(label.into());
+
+        self
+    }
+
+    /// Sets what to do with `before` and `after` constraints referring to a
+    /// label no system carries.
+    pub fn on_missing(mut self, missing: Missing) -> Self {
+        self.missing = missing;
+
+        self
+    }
+
+    /// Runs the graph on a new pool with `threads` worker threads, instead of
+    /// one per CPU.
+    pub fn with_threads(self, threads: usize) -> Self {
+        self.with_pool(Arc::new(ThreadPool::new(threads)))
+    }
+
+    /// Runs the graph on `pool`, which may be shared with other graphs.
+    pub fn with_pool(mut self, pool: Arc<ThreadPool>) -> Self {
+        self.executor = Some(Executor::Pool(pool));
+
+        self
+    }
+
+    /// Runs all systems on the calling thread, in a deterministic order.
+    /// Useful for tests and debugging.
+    pub fn single_threaded(mut self) -> Self {
+        self.executor = Some(Executor::Sequential);
+
+        self
+    }
+
+    /// Computes the schedule and builds the graph.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ordering constraints cannot be satisfied, see
+    /// `try_build`.
+    pub fn build(self) -> Graph<C> {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Validates the ordering constraints, computes the schedule and builds
+    /// the graph.
+    ///
+    /// Systems are ordered by their `before` and `after` constraints first.
+    /// Conflicting systems without such a constraint keep the order in which
+    /// they were added. Every system is then placed in the stage after the
+    /// last stage containing a system it must run after, so systems within a
+    /// stage never conflict.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a constraint refers to a label no system carries (unless
+    /// `Missing::Ignore` is set), or if the constraints form a cycle.
+    pub fn try_build(self) -> Result<Graph<C>, GraphError> {
+        let edges = self.edges()?;
+
+        if let Some(path) = find_cycle(&edges) {
+            return Err(GraphError::Cycle {
+                path: path.into_iter().map(SystemId).collect(),
+            });
+        }
+
+        let order = topological_order(&edges);
+        let mut predecessors = vec![Vec::new(); self.nodes.len()];
+        for (from, successors) in edges.iter().enumerate() {
+            for &to in successors {
+                predecessors[to].push(from);
+            }
+        }
+
+        let mut stage_of = vec![0; self.nodes.len()];
+        for (position, &i) in order.iter().enumerate() {
+            let conflicting = order[..position].iter().filter(|&&earlier| {
+                self.nodes[earlier]
+                    .access
+                    .conflicts_with(&self.nodes[i].access)
+            });
+
+            stage_of[i] = predecessors[i]
+                .iter()
+                .chain(conflicting)
+                .map(|&earlier| stage_of[earlier] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+
+        let mut stages = vec![Vec::new(); stage_of.iter().max().map_or(0, |&s| s + 1)];
+        for (i, &stage) in stage_of.iter().enumerate() {
+            stages[stage].push(SystemId(i));
+        }
+
+        let async_conflicts = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                self.nodes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, other)| {
+                        j != i
+                            && matches!(other.kind, Kind::Async(_))
+                            && other.access.conflicts_with(&node.access)
+                    })
+                    .map(|(j, _)| j)
+                    .collect()
+            })
+            .collect();
+
+        Ok(Graph {
+            nodes: self.nodes,
+            stages,
+            async_conflicts,
+            skipped: Vec::new(),
+            frame: 0,
+            executor: self
+                .executor
+                .unwrap_or_else(|| Executor::Pool(Arc::new(ThreadPool::default()))),
+        })
+    }
+
+    fn push(&mut self, access: Access, kind: Kind<C>) -> SystemId {
+        self.nodes.push(Node {
+            access,
+            kind,
+            labels: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+        });
+
+        SystemId(self.nodes.len() - 1)
+    }
+
+    /// Resolves the `before` and `after` constraints to edges from a system
+    /// to the systems which must run after it.
+    fn edges(&self) -> Result<Vec<Vec<usize>>, GraphError> {
+        let mut labeled: HashMap<&Label, Vec<usize>> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            for label in &node.labels {
+                labeled.entry(label).or_default().push(i);
+            }
+        }
+
+        let mut edges = vec![Vec::new(); self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let constraints = node
+                .before
+                .iter()
+                .map(|label| (label, true))
+                .chain(node.after.iter().map(|label| (label, false)));
+
+            for (label, before) in constraints {
+                let others = match labeled.get(label) {
+                    Some(others) => others,
+                    None if self.missing == Missing::Ignore => continue,
+                    None => {
+                        return Err(GraphError::MissingDependency {
+                            system: SystemId(i),
+                            label: label.clone(),
+                        })
+                    }
+                };
+
+                for &other in others {
+                    match before {
+                        true => edges[i].push(other),
+                        false => edges[other].push(i),
+                    }
+                }
+            }
+        }
+
+        for successors in &mut edges {
+            successors.sort_unstable();
+            successors.dedup();
+        }
+
+        Ok(edges)
+    }
+}
+
+/// Returns a cycle in `edges` if there is one, starting and ending with the
+/// same node.
+fn find_cycle(edges: &[Vec<usize>]) -> Option<Vec<usize>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Unvisited,
+        Visiting,
+        Done,
+    }
+
+    fn visit(
+        node: usize,
+        edges: &[Vec<usize>],
+        marks: &mut [Mark],
+        stack: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        marks[node] = Mark::Visiting;
+        stack.push(node);
+
+        for &next in &edges[node] {
+            match marks[next] {
+                Mark::Unvisited => {
+                    if let Some(cycle) = visit(next, edges, marks, stack) {
+                        return Some(cycle);
+                    }
+                }
+                Mark::Visiting => {
+                    let start = stack.iter().position(|&n| n == next).expect("Unreachable");
+                    let mut cycle = stack[start..].to_vec();
+                    cycle.push(next);
+
+                    return Some(cycle);
+                }
+                Mark::Done => {}
+            }
+        }
+
+        stack.pop();
+        marks[node] = Mark::Done;
+
+        None
+    }
+
+    let mut marks = vec![Mark::Unvisited; edges.len()];
+    let mut stack = Vec::new();
+
+    (0..edges.len()).find_map(|node| match marks[node] {
+        Mark::Unvisited => visit(node, edges, &mut marks, &mut stack),
+        _ => None,
+    })
+}
+
+/// Orders the nodes of the acyclic `edges`, preferring lower indices.
+fn topological_order(edges: &[Vec<usize>]) -> Vec<usize> {
+    let mut in_degree = vec![0; edges.len()];
+    for &to in edges.iter().flatten() {
+        in_degree[to] += 1;
+    }
+
+    let mut ready: BinaryHeap<_> = (0..edges.len())
+        .filter(|&i| in_degree[i] == 0)
+        .map(Reverse)
+        .collect();
+    let mut order = Vec::with_capacity(edges.len());
+
+    while let Some(Reverse(node)) = ready.pop() {
+        order.push(node);
+
+        for &to in &edges[node] {
+            in_degree[to] -= 1;
+            if in_degree[to] == 0 {
+                ready.push(Reverse(to));
+            }
+        }
+    }
+
+    order
+}
+
+impl<C: ?Sized> Default for GraphBuilder<C> {
+    fn default() -> Self {
+        GraphBuilder::new()
+    }
+}
+
+/// A set of systems with a conflict-free parallel schedule.
+///
+/// ```
+/// use nitric_graph::{Access, GraphBuilder, ResourceId};
+/// use nitric_world::World;
+///
+/// let mut world = World::new();
+/// world.insert("dt", 0.5f32);
+/// world.insert("pos", 0.0f32);
+/// world.insert("vel", 2.0f32);
+///
+/// let mut graph = GraphBuilder::new()
+///     .with(
+///         Access::new()
+///             .read(ResourceId::of::<f32, _>("dt"))
+///             .read(ResourceId::of::<f32, _>("vel"))
+///             .write(ResourceId::of::<f32, _>("pos")),
+///         |world: &World<&str>| {
+///             let dt = *world.fetch::<f32, _>("dt");
+///             *world.fetch_mut::<f32, _>("pos") += dt * *world.fetch::<f32, _>("vel");
+///         },
+///     )
+///     .build();
+///
+/// graph.run(&world);
+/// assert_eq!(*world.fetch::<f32, _>("pos"), 1.0);
+/// ```
+pub struct Graph<C: ?Sized> {
+    nodes: Vec<Node<C>>,
+    stages: Vec<Vec<SystemId>>,
+    /// The asynchronous systems each system conflicts with.
+    async_conflicts: Vec<Vec<usize>>,
+    /// The systems skipped in the last frame.
+    skipped: Vec<SystemId>,
+    frame: u64,
+    executor: Executor,
+}
+
+impl<C: ?Sized> Graph<C> {
+    /// Returns the computed schedule. Systems within a stage run in parallel,
+    /// stages run one after another.
+    pub fn stages(&self) -> &[Vec<SystemId>] {
+        &self.stages
+    }
+
+    /// Returns the access declared for `id`.
+    pub fn access(&self, id: SystemId) -> &Access {
+        &self.nodes[id.0].access
+    }
+
+    /// Returns the number of frames run so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Checks if the asynchronous system `id` has a run in progress.
+    pub fn is_running(&self, id: SystemId) -> bool {
+        self.nodes[id.0].is_running()
+    }
+
+    /// Returns the systems which were skipped in the last frame, because
+    /// they conflict with an asynchronous system which had a run in progress.
+    pub fn skipped(&self) -> &[SystemId] {
+        &self.skipped
+    }
+
+    /// Runs one frame: every system is run once, following the schedule,
+    /// and asynchronous systems are started or polled.
+    ///
+    /// Systems conflicting with an asynchronous system which has a run in
+    /// progress are skipped, see `skipped`.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a system once its stage has finished.
+    pub fn run(&mut self, context: &C)
+    where
+        C: Sync,
+    {
+        let pool = match self.executor {
+            Executor::Pool(ref pool) => pool,
+            Executor::Sequential => return self.run_sequential(context),
+        };
+
+        self.frame += 1;
+        self.skipped.clear();
+        let frame = self.frame;
+
+        for stage in 0..self.stages.len() {
+            let (active, skipped) = self.active(stage);
+            self.skipped.extend(skipped);
+            let mut nodes = self
+                .nodes
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| active.contains(i))
+                .map(|(_, node)| node);
+
+            match active.len() {
+                0 => {}
+                // Not worth a trip to the pool
+                1 => nodes.next().expect("Unreachable").run(context, frame),
+                _ => pool.scope(|scope| {
+                    for node in nodes {
+                        scope.execute(move || node.run(context, frame));
+                    }
+                }),
+            }
+        }
+    }
+
+    /// Runs one frame on the calling thread, stage by stage and in insertion
+    /// order within a stage.
+    pub fn run_sequential(&mut self, context: &C) {
+        self.frame += 1;
+        self.skipped.clear();
+
+        for stage in 0..self.stages.len() {
+            let (active, skipped) = self.active(stage);
+            self.skipped.extend(skipped);
+
+            for i in active {
+                self.nodes[i].run(context, self.frame);
+            }
+        }
+    }
+
+    /// Splits the systems of `stage` into the ones to run and the ones which
+    /// are blocked by a run of an asynchronous system.
+    fn active(&self, stage: usize) -> (Vec<usize>, Vec<SystemId>) {
+        let (active, skipped): (Vec<_>, Vec<_>) =
+            self.stages[stage].iter().cloned().partition(|id| {
+                !self.async_conflicts[id.0]
+                    .iter()
+                    .any(|&j| self.nodes[j].is_running())
+            });
+
+        (active.into_iter().map(|id| id.0).collect(), skipped)
+    }
+}
+
+impl<C: ?Sized> Debug for Graph<C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Graph")
+            .field("stages", &self.stages)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::Mutex,
+    };
+
+    use nitric_world::World;
+
+    use super::*;
+    use crate::ResourceId;
+
+    fn id(key: &str) -> ResourceId {
+        ResourceId::of::<u32, _>(key)
+    }
+
+    #[test]
+    fn schedule() {
+        let graph = GraphBuilder::<()>::new()
+            .with(Access::new().write(id("a")), |_: &()| {})
+            .with(Access::new().read(id("b")), |_: &()| {})
+            .with(Access::new().read(id("a")), |_: &()| {})
+            .with(Access::new().read(id("a")).read(id("b")), |_: &()| {})
+            .with(Access::new().write(id("b")), |_: &()| {})
+            .with(Access::new(), |_: &()| {})
+            .single_threaded()
+            .build();
+
+        let stages: Vec<Vec<usize>> = graph
+            .stages()
+            .iter()
+            .map(|ids| ids.iter().map(|id| id.index()).collect())
+            .collect();
+
+        assert_eq!(stages, vec![vec![0, 1, 5], vec![2, 3], vec![4]]);
+    }
+
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    enum Phase {
+        Input,
+        Physics,
+        Render,
+    }
+
+    impl SystemLabel for Phase {}
+
+    fn indices(graph: &Graph<()>) -> Vec<Vec<usize>> {
+        graph
+            .stages()
+            .iter()
+            .map(|ids| ids.iter().map(|id| id.index()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut builder = GraphBuilder::<()>::new().single_threaded();
+        let render = builder.add(Access::new().read(id("pos")), |_: &()| {});
+        builder
+            .label(render, Phase::Render)
+            .after(render, Phase::Physics);
+        let physics = builder.add(Access::new().write(id("pos")), |_: &()| {});
+        builder
+            .label(physics, Phase::Physics)
+            .after(physics, Phase::Input);
+        let input = builder.add(Access::new(), |_: &()| {});
+        builder.label(input, Phase::Input);
+        let independent = builder.add(Access::new().read(id("dt")), |_: &()| {});
+        builder.before(independent, Phase::Render);
+
+        // The conflict with `physics` is ordered by `render`'s constraint
+        assert_eq!(
+            indices(&builder.build()),
+            vec![vec![2, 3], vec![1], vec![0]]
+        );
+    }
+
+    #[test]
+    fn missing_dependency() {
+        let mut builder = GraphBuilder::<()>::new().single_threaded();
+        let a = builder.add(Access::new(), |_: &()| {});
+        builder.after(a, Phase::Input);
+        let b = builder.add(Access::new(), |_: &()| {});
+        builder.label(b, Phase::Render);
+
+        let error = builder.try_build().unwrap_err();
+        assert_eq!(
+            error,
+            GraphError::MissingDependency {
+                system: a,
+                label: Phase::Input.into(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "System #0 depends on `Input`, which no system is labeled with"
+        );
+
+        let mut builder = GraphBuilder::<()>::new()
+            .single_threaded()
+            .on_missing(Missing::Ignore);
+        let a = builder.add(Access::new(), |_: &()| {});
+        builder.after(a, Phase::Input);
+        assert_eq!(indices(&builder.try_build().unwrap()), vec![vec![0]]);
+    }
+
+    #[test]
+    fn cycle() {
+        let mut builder = GraphBuilder::<()>::new().single_threaded();
+        let unrelated = builder.add(Access::new(), |_: &()| {});
+        builder.label(unrelated, Phase::Input);
+        let a = builder.add(Access::new(), |_: &()| {});
+        builder.label(a, Phase::Physics).before(a, Phase::Input);
+        let b = builder.add(Access::new(), |_: &()| {});
+        builder.label(b, Phase::Render).after(b, Phase::Physics);
+        let c = builder.add(Access::new(), |_: &()| {});
+        builder.after(c, Phase::Render).before(c, Phase::Physics);
+
+        let error = builder.try_build().unwrap_err();
+        assert_eq!(
+            error,
+            GraphError::Cycle {
+                path: vec![a, b, c, a]
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Systems depend on each other: #1 -> #2 -> #3 -> #1"
+        );
+    }
+
+    fn counter_graph(builder: GraphBuilder<World<&'static str>>) -> Graph<World<&'static str>> {
+        let mut builder = builder;
+        for key in &["a", "b", "c", "d"] {
+            builder.add(
+                Access::new()
+                    .read(ResourceId::of::<u32, _>("step"))
+                    .write(id(key)),
+                move |world: &World<&str>| {
+                    *world.fetch_mut::<u32, _>(key) += *world.fetch::<u32, _>("step");
+                },
+            );
+        }
+        builder.add(
+            Access::new().write(ResourceId::of::<u32, _>("step")),
+            |world: &World<&str>| *world.fetch_mut::<u32, _>("step") += 1,
+        );
+
+        builder.build()
+    }
+
+    #[test]
+    fn run_parallel() {
+        let mut world = World::new();
+        for &key in &["a", "b", "c", "d"] {
+            world.insert(key, 0u32);
+        }
+        world.insert("step", 1u32);
+
+        let mut graph = counter_graph(GraphBuilder::new().with_threads(4));
+        assert_eq!(graph.stages().len(), 2);
+
+        for _ in 0..3 {
+            graph.run(&world);
+        }
+
+        assert_eq!(*world.fetch::<u32, _>("a"), 1 + 2 + 3);
+        assert_eq!(*world.fetch::<u32, _>("d"), 1 + 2 + 3);
+        assert_eq!(*world.fetch::<u32, _>("step"), 4);
+    }
+
+    #[test]
+    fn run_sequential() {
+        let log = Mutex::new(Vec::new());
+
+        let mut graph = GraphBuilder::new().single_threaded();
+        for i in 0..4 {
+            let access = match i % 2 {
+                0 => Access::new().write(id("even")),
+                _ => Access::new().write(id("odd")),
+            };
+            graph.add(access, move |log: &Mutex<Vec<usize>>| {
+                log.lock().unwrap().push(i)
+            });
+        }
+        let mut graph = graph.build();
+
+        graph.run(&log);
+        graph.run(&log);
+        assert_eq!(log.into_inner().unwrap(), vec![0, 1, 2, 3, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn system_panics() {
+        let mut graph = GraphBuilder::<()>::new()
+            .with_threads(2)
+            .with(Access::new().write(id("a")), |_: &()| {
+                panic!("system failed")
+            })
+            .with(Access::new().write(id("b")), |_: &()| {})
+            .build();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| graph.run(&())));
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<&str>(),
+            Some(&"system failed")
+        );
+    }
+}
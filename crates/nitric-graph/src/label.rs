@@ -0,0 +1,137 @@
+//! Type-safe labels for ordering systems.
+
+use std::{
+    any::{Any, TypeId},
+    fmt::{self, Debug, Formatter},
+    hash::{Hash, Hasher},
+};
+
+/// A type which can be used to label systems, so other systems can be
+/// ordered relative to them (see `GraphBuilder::before` and
+/// `GraphBuilder::after`).
+///
+/// Labels of different types never compare equal, so there are no accidental
+/// name clashes like with string labels.
+///
+/// ```
+/// use nitric_graph::SystemLabel;
+///
+/// #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+/// enum Physics {
+///     Integrate,
+///     Collide,
+/// }
+///
+/// impl SystemLabel for Physics {}
+/// ```
+pub trait SystemLabel: Clone + Debug + Eq + Hash + Send + Sync + 'static {}
+
+trait DynLabel: Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+
+    fn dyn_eq(&self, other: &dyn DynLabel) -> bool;
+
+    fn dyn_hash(&self, state: &mut dyn Hasher);
+
+    fn dyn_clone(&self) -> Box<dyn DynLabel>;
+}
+
+impl<L: SystemLabel> DynLabel for L {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn dyn_eq(&self, other: &dyn DynLabel) -> bool {
+        other.as_any().downcast_ref::<L>() == Some(self)
+    }
+
+    fn dyn_hash(&self, mut state: &mut dyn Hasher) {
+        TypeId::of::<L>().hash(&mut state);
+        self.hash(&mut state);
+    }
+
+    fn dyn_clone(&self) -> Box<dyn DynLabel> {
+        Box::new(self.clone())
+    }
+}
+
+/// A type-erased `SystemLabel`.
+pub struct Label(Box<dyn DynLabel>);
+
+impl Label {
+    /// Erases the type of `label`.
+    pub fn new<L: SystemLabel>(label: L) -> Self {
+        Label(Box::new(label))
+    }
+
+    /// Returns the label if it is of type `L`.
+    pub fn downcast_ref<L: SystemLabel>(&self) -> Option<&L> {
+        self.0.as_any().downcast_ref()
+    }
+}
+
+impl<L: SystemLabel> From<L> for Label {
+    fn from(label: L) -> Self {
+        Label::new(label)
+    }
+}
+
+impl Clone for Label {
+    fn clone(&self) -> Self {
+        Label(self.0.dyn_clone())
+    }
+}
+
+impl Debug for Label {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl PartialEq for Label {
+    fn eq(&self, other: &Label) -> bool {
+        self.0.dyn_eq(&*other.0)
+    }
+}
+
+impl Eq for Label {}
+
+impl Hash for Label {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.dyn_hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    struct A(u32);
+
+    impl SystemLabel for A {}
+
+    #[derive(Clone, Debug, Eq, Hash, PartialEq)]
+    struct B(u32);
+
+    impl SystemLabel for B {}
+
+    #[test]
+    fn equality() {
+        assert_eq!(Label::new(A(1)), Label::new(A(1)));
+        assert_ne!(Label::new(A(1)), Label::new(A(2)));
+        assert_ne!(Label::new(A(1)), Label::new(B(1)));
+
+        let set: HashSet<Label> = vec![A(1).into(), A(1).into(), B(1).into()]
+            .into_iter()
+            .collect();
+        assert_eq!(set.len(), 2);
+
+        let label = Label::new(B(3)).clone();
+        assert_eq!(label.downcast_ref::<B>(), Some(&B(3)));
+        assert_eq!(label.downcast_ref::<A>(), None);
+        assert_eq!(format!("{:?}", label), "B(3)");
+    }
+}
@@ -0,0 +1,69 @@
+#![deny(unused_must_use)]
+
+//! # `nitric-graph`
+//!
+//! Parallel execution of systems. A system is anything implementing
+//! `System`, e.g. a closure taking a shared context like a
+//! `nitric_world::World`.
+//!
+//! ## Scheduling
+//!
+//! Every system declares the resources it reads and writes with an `Access`.
+//! Resources are either stored in a `World` (identified by type and key) or
+//! guarded by a `nitric-lock` lock (identified by its `LockId`). Two systems
+//! conflict if one of them writes a resource the other one accesses.
+//!
+//! `GraphBuilder::build` groups the systems into stages of non-conflicting
+//! systems; conflicting systems run in the order they were added, unless
+//! ordered otherwise (see below). The stages are executed one after another,
+//! with the systems of a stage running in parallel on a `ThreadPool`. For
+//! tests, `GraphBuilder::single_threaded` runs everything on the calling
+//! thread in a deterministic order.
+//!
+//! ## Dependencies
+//!
+//! Systems can be tagged with typed labels (see `SystemLabel`) and ordered
+//! relative to other labels with `GraphBuilder::before` and
+//! `GraphBuilder::after`. Constraints may refer to systems which are added
+//! later; they are resolved by `GraphBuilder::try_build`, which reports
+//! cycles and missing dependencies as a `GraphError`. Constraints on missing
+//! labels can be ignored instead with `GraphBuilder::on_missing`.
+//!
+//! ## Asynchronous systems
+//!
+//! An `AsyncSystem` may take several frames (calls of `Graph::run`) to
+//! complete. It is started and then polled once per frame, until it is ready
+//! or until the frame given by its `Completion`, in which the graph blocks
+//! until it is done. While it is running, the resources it declared are
+//! claimed in the context (see `Claimable`), so code outside of the graph
+//! cannot borrow them, and the graph skips systems conflicting with its
+//! access (see `Graph::skipped`).
+//!
+//! Note that accesses are not enforced; a system accessing resources it did
+//! not declare will cause borrow errors or lock contention.
+//!
+//! ## Features
+//!
+//! * `futures`: Adds `FutureSystem`, which runs a `Future` as an
+//!   `AsyncSystem`.
+
+pub use self::{
+    access::{Access, ResourceId},
+    async_system::{AsyncSystem, Claimable, Completion},
+    error::GraphError,
+    graph::{Graph, GraphBuilder, Missing, SystemId},
+    label::{Label, SystemLabel},
+    pool::{Scope, ThreadPool},
+    system::System,
+};
+
+#[cfg(feature = "futures")]
+pub use self::async_system::FutureSystem;
+
+mod access;
+mod async_system;
+mod error;
+mod graph;
+mod label;
+mod pool;
+mod system;
@@ -0,0 +1,230 @@
+//! A minimal thread pool with scoped jobs.
+
+use std::{
+    any::Any,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of worker threads executing jobs.
+///
+/// A pool can be shared between several graphs, but must not be used from
+/// within one of its own jobs, because waiting for a scope from a worker
+/// thread can deadlock.
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// Spawns a pool with `threads` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "A thread pool needs at least one thread");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+
+                thread::Builder::new()
+                    .name(format!("nitric-graph-{}", i))
+                    .spawn(move || loop {
+                        // Jobs catch their panics, so the mutex is never poisoned
+                        let job = receiver.lock().unwrap().recv();
+
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+
+        ThreadPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Returns the number of worker threads.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Creates a scope in which jobs borrowing from the environment can be
+    /// executed. Waits for all of them to finish before returning.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of the first job that panicked, after all jobs have
+    /// finished.
+    pub fn scope<'s, F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'_, 's>) -> R,
+    {
+        struct WaitGuard<'a, 'p, 's>(&'a Scope<'p, 's>);
+
+        impl Drop for WaitGuard<'_, '_, '_> {
+            fn drop(&mut self) {
+                self.0.wait();
+            }
+        }
+
+        let scope = Scope {
+            pool: self,
+            state: Default::default(),
+            marker: PhantomData,
+        };
+
+        let result = {
+            let _guard = WaitGuard(&scope);
+
+            f(&scope)
+        };
+
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+
+        result
+    }
+}
+
+impl Default for ThreadPool {
+    /// Spawns one worker thread per available CPU.
+    fn default() -> Self {
+        ThreadPool::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the channel stops the workers
+        self.sender.take();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+/// Executes jobs which may borrow data living for `'s`, see
+/// `ThreadPool::scope`.
+pub struct Scope<'p, 's> {
+    pool: &'p ThreadPool,
+    state: Arc<ScopeState>,
+    /// Makes `'s` invariant.
+    marker: PhantomData<&'s mut &'s ()>,
+}
+
+impl<'s> Scope<'_, 's> {
+    /// Executes `job` on one of the worker threads.
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 's,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+
+        let state = self.state.clone();
+        let job: Box<dyn FnOnce() + Send + 's> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                state.panic.lock().unwrap().get_or_insert(payload);
+            }
+
+            let mut pending = state.pending.lock().unwrap();
+            *pending -= 1;
+            if *pending == 0 {
+                state.done.notify_all();
+            }
+        });
+        // Safe because `ThreadPool::scope` waits for all jobs before `'s` ends
+        let job: Job = unsafe { mem::transmute(job) };
+
+        self.pool
+            .sender
+            .as_ref()
+            .expect("Unreachable")
+            .send(job)
+            .expect("Worker threads are gone");
+    }
+
+    fn wait(&self) {
+        let mut pending = self.state.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.state.done.wait(pending).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn scope() {
+        let pool = ThreadPool::new(3);
+        let counter = AtomicUsize::new(0);
+        let mut values = vec![0; 10];
+
+        pool.scope(|scope| {
+            for (i, value) in values.iter_mut().enumerate() {
+                let counter = &counter;
+                scope.execute(move || {
+                    *value = i * 2;
+                    counter.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        });
+
+        assert_eq!(counter.into_inner(), 10);
+        assert_eq!(values, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panic_propagates() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|scope| {
+                scope.execute(|| panic!("job failed"));
+                scope.execute(|| {
+                    finished.fetch_add(1, Ordering::Relaxed);
+                });
+            })
+        }));
+
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<&str>(),
+            Some(&"job failed")
+        );
+        assert_eq!(finished.load(Ordering::Relaxed), 1);
+
+        // The pool is still usable afterwards
+        pool.scope(|scope| scope.execute(|| {}));
+    }
+}
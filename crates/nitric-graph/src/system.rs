@@ -0,0 +1,18 @@
+/// A unit of work executed by a `Graph`, given a shared context `C` (e.g. a
+/// `nitric_world::World`).
+///
+/// Implemented for all `FnMut(&C) -> R`; the return value is discarded.
+pub trait System<C: ?Sized>: Send {
+    /// Runs the system once.
+    fn run(&mut self, context: &C);
+}
+
+impl<C, F, R> System<C> for F
+where
+    C: ?Sized,
+    F: FnMut(&C) -> R + Send,
+{
+    fn run(&mut self, context: &C) {
+        self(context);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    slice,
+    time::{Duration, Instant},
+};
+
+use lock_api::RawMutex;
+use parking_lot::{Condvar as RawCondvar, Mutex as InnerMutex};
+
+use crate::{
+    group::held_guards,
+    join::{acquire, poison_result},
+    LockResult, Mutex, MutexGuard, PoisonError, WriteLock,
+};
+
+/// A condition variable that can be used with the guards of a nitric `Mutex`.
+///
+/// Waiting releases the mutex and re-acquires it once the thread has been
+/// notified. Since this is only deadlock-free if no other locks are held,
+/// waiting while the thread holds other guards is refused with a panic.
+#[derive(Default)]
+pub struct Condvar {
+    inner: RawCondvar,
+    lock: InnerMutex<()>,
+}
+
+/// Whether a timed wait on a `Condvar` returned because of a timeout.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait timed out.
+    pub fn timed_out(self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Releases the mutex of `guard` and blocks until this condition variable
+    /// is notified, then re-acquires the mutex.
+    ///
+    /// Like `std::sync::Condvar`, this is subject to spurious wakeups.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoisonError` carrying the guard if the mutex is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread holds lock guards other than `guard`.
+    pub fn wait<'a, T, R: RawMutex>(
+        &self,
+        guard: MutexGuard<'a, T, R>,
+    ) -> LockResult<MutexGuard<'a, T, R>> {
+        let (guard, _) = self.wait_until(guard, None);
+
+        guard
+    }
+
+    /// Blocks until `condition` returns `false`, waiting for notifications
+    /// in between. The condition is checked while holding the mutex.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoisonError` carrying the guard if the mutex is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread holds lock guards other than `guard`.
+    pub fn wait_while<'a, T, R: RawMutex, F>(
+        &self,
+        mut guard: MutexGuard<'a, T, R>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T, R>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut guard) {
+            guard = self.wait(guard)?;
+        }
+
+        Ok(guard)
+    }
+
+    /// Like `wait`, but gives up waiting after `timeout`. A timeout too large
+    /// to be represented as a deadline waits without a timeout.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `PoisonError` carrying the guard and the timeout result if
+    /// the mutex is poisoned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the current thread holds lock guards other than `guard`.
+    pub fn wait_timeout<'a, T, R: RawMutex>(
+        &self,
+        guard: MutexGuard<'a, T, R>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T, R>, WaitTimeoutResult)> {
+        let (guard, timed_out) = self.wait_until(guard, Instant::now().checked_add(timeout));
+        let result = WaitTimeoutResult(timed_out);
+
+        guard
+            .map(|guard| (guard, result))
+            .map_err(|e| PoisonError::new((e.into_inner(), result)))
+    }
+
+    /// Wakes up one thread blocked on this condition variable.
+    pub fn notify_one(&self) {
+        let _lock = self.lock.lock();
+
+        self.inner.notify_one();
+    }
+
+    /// Wakes up all threads blocked on this condition variable.
+    pub fn notify_all(&self) {
+        let _lock = self.lock.lock();
+
+        self.inner.notify_all();
+    }
+
+    fn wait_until<'a, T, R: RawMutex>(
+        &self,
+        guard: MutexGuard<'a, T, R>,
+        deadline: Option<Instant>,
+    ) -> (LockResult<MutexGuard<'a, T, R>>, bool) {
+        if held_guards() != 1 {
+            // Release the mutex first, so the panic doesn't poison it
+            drop(guard);
+
+            panic!("Cannot wait on a condition variable while holding other locks");
+        }
+
+        let mutex: &'a Mutex<T, R> = MutexGuard::mutex(&guard);
+
+        // Take the internal lock before releasing the mutex, so a thread
+        // modifying the state and notifying afterwards cannot be missed.
+        let mut lock = self.lock.lock();
+        drop(guard);
+
+        let timed_out = match deadline {
+            Some(deadline) => self.inner.wait_until(&mut lock, deadline).timed_out(),
+            None => {
+                self.inner.wait(&mut lock);
+
+                false
+            }
+        };
+
+        drop(lock);
+
+        // No other locks are held, so re-acquiring the mutex cannot violate
+        // the lock order.
+        let guard = unsafe {
+            let info = WriteLock::lock_info(&mutex);
+            acquire(slice::from_ref(&info));
+
+            poison_result(mutex.acquire_guard(), info.poison.get())
+        };
+
+        (guard, timed_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn producer_consumer() {
+        let mut group = LockGroup::new();
+        let queue = Arc::new(group.mutex(Vec::new()));
+        let condvar = Arc::new(Condvar::new());
+        let group = Arc::new(group);
+
+        let consumer = {
+            let group = group.clone();
+            let queue = queue.clone();
+            let condvar = condvar.clone();
+
+            thread::spawn(move || {
+                let mut token = group.token();
+                let mut sum = 0;
+
+                for _ in 0..100 {
+                    let (guard,) = (queue.write(),).lock(&mut token).unwrap();
+                    let mut guard = condvar.wait_while(guard, |q| q.is_empty()).unwrap();
+
+                    sum += guard.pop().unwrap();
+                }
+
+                sum
+            })
+        };
+
+        let mut token = group.token();
+
+        for i in 0..100 {
+            (queue.write(),).lock(&mut token).unwrap().0.push(i);
+            condvar.notify_one();
+        }
+
+        assert_eq!(consumer.join().unwrap(), (0..100).sum());
+    }
+
+    #[test]
+    fn timeout() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex = group.mutex(5);
+        let condvar = Condvar::new();
+
+        let (guard,) = (mutex.write(),).lock(&mut token).unwrap();
+        let (guard, result) = condvar
+            .wait_timeout(guard, Duration::from_millis(10))
+            .unwrap();
+
+        assert!(result.timed_out());
+        assert_eq!(*guard, 5);
+    }
+
+    #[test]
+    fn max_timeout() {
+        let mut group = LockGroup::new();
+        let mutex = Arc::new(group.mutex(false));
+        let condvar = Arc::new(Condvar::new());
+        let group = Arc::new(group);
+
+        let notifier = {
+            let group = group.clone();
+            let mutex = mutex.clone();
+            let condvar = condvar.clone();
+
+            thread::spawn(move || {
+                let mut token = group.token();
+
+                *(mutex.write(),).lock(&mut token).unwrap().0 = true;
+                condvar.notify_all();
+            })
+        };
+
+        let mut token = group.token();
+        let (mut guard,) = (mutex.write(),).lock(&mut token).unwrap();
+        while !*guard {
+            let (next, result) = condvar.wait_timeout(guard, Duration::MAX).unwrap();
+
+            assert!(!result.timed_out());
+            guard = next;
+        }
+        drop(guard);
+
+        notifier.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "poison")]
+    fn refusal_does_not_poison() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex_a = group.mutex(1);
+        let mutex_b = group.mutex(2);
+        let condvar = Condvar::new();
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let (a, _b) = lock2(&mut token, mutex_a.write(), mutex_b.write()).unwrap();
+
+            let _ = condvar.wait(a);
+        }));
+
+        assert!(result.is_err());
+        assert!(!mutex_a.is_poisoned());
+    }
+
+    #[test]
+    #[should_panic(expected = "holding other locks")]
+    fn refuse_other_guards() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex_a = group.mutex(1);
+        let mutex_b = group.mutex(2);
+        let condvar = Condvar::new();
+
+        let (a, _b) = lock2(&mut token, mutex_a.write(), mutex_b.write()).unwrap();
+
+        let _ = condvar.wait(a);
+    }
+}
@@ -0,0 +1,500 @@
+//! Runtime deadlock detection
+//!
+//! Only available with the `deadlock-detection` feature enabled.
+//!
+//! The detector keeps track of the locks held by every thread and records the
+//! order in which locks are acquired, across all threads. It panics with a
+//! report if
+//!
+//! * a lock of `nitric-lock` is acquired while a lock with a greater or equal
+//!   `LockId` is held by the same thread (out-of-order acquisition), or
+//! * the recorded acquisition order contains a cycle, meaning there is an
+//!   interleaving of the involved threads that deadlocks.
+//!
+//! Locks of `nitric-lock` are registered automatically, and are considered
+//! held by a thread as long as it has a guard of them. Locks that are
+//! acquired by other means can take part in the detection by calling
+//! `lock_requested`, `lock_acquired` and `lock_released` with a
+//! `DetectorId::external` ID.
+//!
+//! The report contains a backtrace for every lock involved, showing where it
+//! was acquired. Like `std::backtrace::Backtrace::capture`, these are only
+//! captured if the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment
+//! variable is set.
+//!
+//! The recorded acquisition order of a lock is forgotten when it is dropped.
+//! External locks have to be passed to `lock_dropped` for that, otherwise a
+//! lock which reuses the address of a dropped one may cause false positives.
+
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter, Write},
+    sync::OnceLock,
+    thread::{self, ThreadId},
+};
+
+use parking_lot::Mutex;
+
+use crate::{group::held_locks, LockId};
+
+thread_local! {
+    /// The addresses of the external locks held by the current thread. Locks
+    /// of `nitric-lock` are tracked by their guards instead.
+    static HELD_EXTERNAL: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Identifies a lock tracked by the deadlock detector.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DetectorId {
+    /// A lock of `nitric-lock`, which has a place in the global lock order.
+    Lock(LockId),
+    /// A lock acquired by other means, identified by its address.
+    External(usize),
+}
+
+impl DetectorId {
+    /// Creates the ID of an external lock from its address.
+    pub fn external<T: ?Sized>(lock: &T) -> Self {
+        DetectorId::External(lock as *const T as *const () as usize)
+    }
+}
+
+impl Display for DetectorId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            DetectorId::Lock(id) => write!(f, "lock {} of group {}", id.index(), id.group()),
+            DetectorId::External(addr) => write!(f, "external lock at {:#x}", addr),
+        }
+    }
+}
+
+impl From<LockId> for DetectorId {
+    fn from(id: LockId) -> Self {
+        DetectorId::Lock(id)
+    }
+}
+
+/// Forgets about a lock of `nitric-lock` once it is dropped.
+#[derive(Debug)]
+pub(crate) struct Registration(pub LockId);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        lock_dropped(self.0);
+    }
+}
+
+struct Holder {
+    thread: ThreadId,
+    name: Option<String>,
+    backtrace: Backtrace,
+}
+
+#[derive(Default)]
+struct State {
+    holders: HashMap<DetectorId, Vec<Holder>>,
+    /// The locks acquired while holding a lock, by the held lock.
+    order: HashMap<DetectorId, HashSet<DetectorId>>,
+    /// The reverse of `order`, so dropped locks can be removed quickly.
+    acquired_after: HashMap<DetectorId, HashSet<DetectorId>>,
+}
+
+impl State {
+    /// Searches a path from `from` to `to` in the recorded acquisition order.
+    fn path(&self, from: DetectorId, to: DetectorId) -> Option<Vec<DetectorId>> {
+        let mut visited = HashSet::new();
+        let mut path = vec![from];
+
+        self.search(to, &mut visited, &mut path).then_some(path)
+    }
+
+    fn search(
+        &self,
+        to: DetectorId,
+        visited: &mut HashSet<DetectorId>,
+        path: &mut Vec<DetectorId>,
+    ) -> bool {
+        let current = *path.last().unwrap();
+
+        if current == to {
+            return true;
+        }
+
+        if !visited.insert(current) {
+            return false;
+        }
+
+        for &next in self.order.get(&current).into_iter().flatten() {
+            path.push(next);
+
+            if self.search(to, visited, path) {
+                return true;
+            }
+
+            path.pop();
+        }
+
+        false
+    }
+
+    fn write_holders(&self, report: &mut String, ids: &[DetectorId]) {
+        let mut seen = HashSet::new();
+
+        for id in ids.iter().filter(|id| seen.insert(**id)) {
+            for holder in self.holders.get(id).into_iter().flatten() {
+                let _ = write!(
+                    report,
+                    "\n{} is held by thread `{}` ({:?})",
+                    id,
+                    holder.name.as_ref().map_or("<unnamed>", String::as_str),
+                    holder.thread,
+                );
+
+                let _ = match holder.backtrace.status() {
+                    BacktraceStatus::Captured => {
+                        writeln!(report, ", acquired at:\n{}", holder.backtrace)
+                    }
+                    _ => writeln!(report, " (set `RUST_BACKTRACE=1` for a backtrace)"),
+                };
+            }
+        }
+    }
+}
+
+fn state() -> &'static Mutex<State> {
+    static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+    STATE.get_or_init(Default::default)
+}
+
+fn current_thread() -> String {
+    let thread = thread::current();
+
+    format!(
+        "`{}` ({:?})",
+        thread.name().unwrap_or("<unnamed>"),
+        thread.id()
+    )
+}
+
+/// The locks held by the current thread.
+fn held() -> Vec<DetectorId> {
+    let mut held: Vec<_> = held_locks().into_iter().map(DetectorId::Lock).collect();
+    HELD_EXTERNAL
+        .with(|external| held.extend(external.borrow().iter().cloned().map(DetectorId::External)));
+
+    held
+}
+
+/// Records that the current thread is about to acquire the lock `id`.
+///
+/// # Panics
+///
+/// Panics with a report if acquiring the lock could result in a deadlock.
+pub fn lock_requested(id: impl Into<DetectorId>) {
+    request(id.into(), &held());
+}
+
+/// Records that the current thread is about to upgrade the lock `id`, which
+/// it holds already, and waits for the other holders to release it.
+///
+/// # Panics
+///
+/// Panics with a report if waiting could result in a deadlock.
+pub(crate) fn upgrade_requested(id: LockId) {
+    let id = DetectorId::Lock(id);
+    let held: Vec<_> = held().into_iter().filter(|h| *h != id).collect();
+
+    request(id, &held);
+}
+
+fn request(id: DetectorId, held: &[DetectorId]) {
+    let report = {
+        let mut state = state().lock();
+
+        match check(&state, id, held) {
+            Some(report) => report,
+            None => {
+                for &h in held {
+                    state.order.entry(h).or_default().insert(id);
+                    state.acquired_after.entry(id).or_default().insert(h);
+                }
+
+                return;
+            }
+        }
+    };
+
+    panic!("{}", report);
+}
+
+fn check(state: &State, id: DetectorId, held: &[DetectorId]) -> Option<String> {
+    if let DetectorId::Lock(lock) = id {
+        let violation = held.iter().find(|h| match **h {
+            DetectorId::Lock(h) => h >= lock,
+            DetectorId::External(_) => false,
+        });
+
+        if let Some(&h) = violation {
+            let mut report = format!(
+                "Out-of-order lock acquisition: thread {} requested {} while holding {}\n",
+                current_thread(),
+                id,
+                h
+            );
+            state.write_holders(&mut report, held);
+
+            return Some(report);
+        }
+    }
+
+    held.iter()
+        .filter_map(|&h| state.path(id, h))
+        .next()
+        .map(|path| {
+            let mut report = format!(
+                "Lock order cycle: thread {} requested {} while holding {}\n\nCycle: {}",
+                current_thread(),
+                id,
+                path.last().unwrap(),
+                path.iter()
+                    .chain(Some(&id))
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            );
+            report.push('\n');
+            state.write_holders(&mut report, &path);
+
+            report
+        })
+}
+
+/// Records that the current thread acquired the lock `id`.
+pub fn lock_acquired(id: impl Into<DetectorId>) {
+    let id = id.into();
+    let holder = Holder {
+        thread: thread::current().id(),
+        name: thread::current().name().map(ToOwned::to_owned),
+        backtrace: Backtrace::capture(),
+    };
+
+    if let DetectorId::External(addr) = id {
+        HELD_EXTERNAL.with(|held| held.borrow_mut().push(addr));
+    }
+    state().lock().holders.entry(id).or_default().push(holder);
+}
+
+/// Records that the current thread released the lock `id`.
+pub fn lock_released(id: impl Into<DetectorId>) {
+    let id = id.into();
+    let thread = thread::current().id();
+
+    if let DetectorId::External(addr) = id {
+        HELD_EXTERNAL.with(|held| {
+            let mut held = held.borrow_mut();
+
+            if let Some(pos) = held.iter().rposition(|h| *h == addr) {
+                held.remove(pos);
+            }
+        });
+    }
+
+    let mut state = state().lock();
+
+    if let Some(holders) = state.holders.get_mut(&id) {
+        if let Some(pos) = holders.iter().rposition(|h| h.thread == thread) {
+            holders.remove(pos);
+        }
+
+        if holders.is_empty() {
+            state.holders.remove(&id);
+        }
+    }
+}
+
+/// Forgets the recorded acquisition order of the lock `id`, which must not be
+/// held anymore. Called automatically for locks of `nitric-lock` when they
+/// are dropped.
+pub fn lock_dropped(id: impl Into<DetectorId>) {
+    let id = id.into();
+    let mut state = state().lock();
+
+    state.holders.remove(&id);
+    for next in state.order.remove(&id).into_iter().flatten() {
+        if let Some(after) = state.acquired_after.get_mut(&next) {
+            after.remove(&id);
+        }
+    }
+    for prev in state.acquired_after.remove(&id).into_iter().flatten() {
+        if let Some(order) = state.order.get_mut(&prev) {
+            order.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn in_order() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let a = group.mutex(1);
+        let b = group.mutex(2);
+
+        for _ in 0..2 {
+            let (a, b) = lock2(&mut token, b.read(), a.read()).unwrap();
+
+            assert_eq!(*a + *b, 3);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Out-of-order")]
+    fn out_of_order() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let a = group.mutex(1);
+        let b = group.mutex(2);
+
+        let _b = (b.read(),).lock(&mut token).unwrap();
+        lock_requested(a.lock_id());
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn external_cycle() {
+        let x = std::sync::Mutex::new(());
+        let y = std::sync::Mutex::new(());
+
+        let (x, y) = (DetectorId::external(&x), DetectorId::external(&y));
+
+        thread::spawn(move || {
+            lock_requested(x);
+            lock_acquired(x);
+            lock_requested(y);
+            lock_acquired(y);
+            lock_released(y);
+            lock_released(x);
+        })
+        .join()
+        .unwrap();
+
+        lock_requested(y);
+        lock_acquired(y);
+        lock_requested(x);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn mixed_cycle() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let a = group.mutex(1);
+        let b = group.mutex(2);
+        let x = std::sync::Mutex::new(());
+        let x = DetectorId::external(&x);
+
+        {
+            let (_a, _b) = lock2(&mut token, a.read(), b.read()).unwrap();
+
+            lock_requested(x);
+            lock_acquired(x);
+            lock_released(x);
+        }
+
+        lock_requested(x);
+        lock_acquired(x);
+        let _ = lock2(&mut token, a.read(), b.read()).unwrap();
+    }
+
+    #[test]
+    fn report_releases_set() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        use lock_api::RawMutex;
+
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let a = group.mutex(1);
+        let b = group.mutex(2);
+        let x = std::sync::Mutex::new(());
+        let x = DetectorId::external(&x);
+
+        {
+            let _b = (b.read(),).lock(&mut token).unwrap();
+
+            lock_requested(x);
+            lock_acquired(x);
+            lock_released(x);
+        }
+
+        lock_requested(x);
+        lock_acquired(x);
+        // Only `b` closes the cycle, after `a` has been checked
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _ = lock2(&mut token, a.read(), b.read());
+        }));
+        lock_released(x);
+
+        assert!(result.is_err());
+        unsafe {
+            assert!(RawMutex::try_lock(a.raw()));
+            RawMutex::unlock(a.raw());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn upgrade_cycle() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let lock = group.rw_lock(1);
+        let x = std::sync::Mutex::new(());
+        let x = DetectorId::external(&x);
+
+        let (guard,) = (lock.upgradable(),).lock(&mut token).unwrap();
+        lock_requested(x);
+        lock_acquired(x);
+
+        // A reader of `lock` acquiring `x` would never let the upgrade finish.
+        RwLockUpgradableReadGuard::upgrade(guard);
+    }
+
+    #[test]
+    fn dropped_locks() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let a = group.mutex(1);
+        let id = DetectorId::Lock(a.lock_id());
+        let x = std::sync::Mutex::new(());
+        let x = DetectorId::external(&x);
+
+        {
+            let _a = (a.read(),).lock(&mut token).unwrap();
+
+            lock_requested(x);
+            lock_acquired(x);
+            lock_released(x);
+        }
+
+        assert!(state().lock().order[&id].contains(&x));
+        drop(a);
+        assert!(!state().lock().order.contains_key(&id));
+        assert!(!state().lock().acquired_after[&x].contains(&id));
+
+        lock_dropped(x);
+        assert!(!state().lock().acquired_after.contains_key(&x));
+    }
+}
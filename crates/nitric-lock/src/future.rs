@@ -0,0 +1,432 @@
+//! Asynchronous joined locking
+//!
+
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use crate::{
+    join::{is_poisoned, poison_result, sort_locks},
+    LockInfo, LockResult, LockSet, TaskToken,
+};
+
+/// Future returned by `LockSet::lock_async`, resolving to the guards of the
+/// lock set once all locks have been acquired. Like `LockSet::lock`, it
+/// resolves to a `PoisonError` carrying the guards if one of the locks is
+/// poisoned.
+///
+/// Every poll either acquires all locks or none of them, so the future holds
+/// no locks while it is pending, and dropping it never has to release any.
+///
+/// This future does not depend on a particular executor; it only relies on
+/// the waker passed to `poll`.
+///
+/// # Threads
+///
+/// The future borrows a `TaskToken` instead of the `LockToken` of the
+/// current thread, so it is `Send` and many of them can be pending on the
+/// same thread. The guards it resolves to are bound to the thread that
+/// polled the future, though, so on a work-stealing executor they have to be
+/// dropped before the next `.await`:
+///
+/// ```compile_fail,E0277
+/// use std::future::Future;
+///
+/// use nitric_lock::{LockGroup, LockSet, WriteLock};
+///
+/// fn output_is_send<F: Future>(_: F)
+/// where
+///     F::Output: Send,
+/// {
+/// }
+///
+/// let mut group = LockGroup::new();
+/// let mutex = group.mutex(0);
+/// let mut token = group.task_token();
+///
+/// output_is_send((mutex.write(),).lock_async(&mut token));
+/// ```
+#[must_use = "futures do nothing unless polled"]
+pub struct LockFuture<'token, S> {
+    set: Option<S>,
+    /// The locks of `set`, sorted by their IDs.
+    locks: Vec<LockInfo<'token>>,
+    /// The index of the first lock that was contended, and when the future
+    /// started waiting for it.
+    waiting: Option<(usize, Instant)>,
+    marker: PhantomData<&'token mut TaskToken>,
+}
+
+impl<'token, S> LockFuture<'token, S>
+where
+    S: LockSet<'token>,
+{
+    /// # Panics
+    ///
+    /// Panics if the same lock is contained twice.
+    pub(crate) fn new(set: S) -> Self {
+        let mut locks = unsafe { set.lock_infos() };
+        sort_locks(&mut locks);
+
+        LockFuture {
+            set: Some(set),
+            locks,
+            waiting: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'token, S> Future for LockFuture<'token, S>
+where
+    S: LockSet<'token>,
+{
+    type Output = LockResult<S::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        assert!(this.set.is_some(), "`LockFuture` polled after completion");
+
+        for (i, info) in this.locks.iter().enumerate() {
+            if !info.guard.try_lock() {
+                info.waiters.register(cx.waker());
+
+                // the lock might have been released before we registered
+                if !info.guard.try_lock() {
+                    for info in &this.locks[..i] {
+                        unsafe { info.guard.unlock() };
+                        info.waiters.wake_all();
+                    }
+                    this.waiting.get_or_insert_with(|| (i, Instant::now()));
+
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        if let Some((i, start)) = this.waiting.take() {
+            this.locks[i].stats.record_wait(start.elapsed());
+        }
+        for info in &this.locks {
+            info.stats.record_acquired();
+
+            #[cfg(feature = "deadlock-detection")]
+            crate::deadlock::lock_acquired(info.id);
+        }
+
+        let poisoned = is_poisoned(&this.locks);
+        let set = this.set.take().unwrap();
+
+        Poll::Ready(poison_result(unsafe { set.lock_unchecked() }, poisoned))
+    }
+}
+
+impl<S> Unpin for LockFuture<'_, S> {}
+
+// The lock infos only refer to the locks of `set`, and none of them is held
+// while the future is pending, so it can move to another thread with `set`.
+unsafe impl<S: Send> Send for LockFuture<'_, S> {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        task::{Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    use super::*;
+    use crate::*;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Polls all `futures` on the current thread until they are complete.
+    fn block_on_all(mut futures: Vec<Pin<Box<dyn Future<Output = ()> + '_>>>) {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        while !futures.is_empty() {
+            futures.retain_mut(|future| future.as_mut().poll(&mut cx).is_pending());
+
+            if !futures.is_empty() {
+                thread::park();
+            }
+        }
+    }
+
+    /// Returns `Pending` once, waking the task right away.
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+
+            self.0 = true;
+            cx.waker().wake_by_ref();
+
+            Poll::Pending
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Locks `mutex` on another thread until a message is sent to the
+    /// returned channel.
+    fn hold<T: Send + Sync + 'static>(
+        group: &Arc<LockGroup>,
+        mutex: &Arc<Mutex<T>>,
+    ) -> (mpsc::Sender<()>, thread::JoinHandle<()>) {
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+
+        let group = group.clone();
+        let mutex = mutex.clone();
+        let handle = thread::spawn(move || {
+            let mut token = group.token();
+            let (_guard,) = (mutex.write(),).lock(&mut token).unwrap();
+
+            locked_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        });
+
+        locked_rx.recv().unwrap();
+
+        (release_tx, handle)
+    }
+
+    #[test]
+    fn lock2_async_ready() {
+        let mut group = LockGroup::new();
+        let mut token = group.task_token();
+
+        let mutex_b = group.mutex(35);
+        let mutex_a = group.mutex(42);
+
+        let (a, mut b) =
+            block_on(lock2_async(&mut token, mutex_a.read(), mutex_b.write())).unwrap();
+
+        assert_eq!(*a, 42);
+        assert_eq!(*b, 35);
+
+        *b = 15;
+    }
+
+    #[test]
+    fn lock_async_wakes() {
+        let mut group = LockGroup::new();
+        let mutex_a = Arc::new(group.mutex(1));
+        let mutex_b = Arc::new(group.mutex(2));
+        let group = Arc::new(group);
+
+        let mut token = group.task_token();
+        let (release, handle) = hold(&group, &mutex_b);
+
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = (mutex_a.write(), mutex_b.write()).lock_async(&mut token);
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+        release.send(()).unwrap();
+        handle.join().unwrap();
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        match Pin::new(&mut future).poll(&mut cx) {
+            Poll::Ready(Ok((mut a, b))) => *a += *b,
+            Poll::Ready(Err(_)) => panic!("Lock should not be poisoned"),
+            Poll::Pending => panic!("Lock should be available"),
+        }
+        drop(future);
+
+        let mut token = group.token();
+        assert_eq!(*(mutex_a.read(),).lock(&mut token).unwrap().0, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn stats_count_wait_once() {
+        let mut group = LockGroup::new();
+        let mutex = Arc::new(group.mutex(1));
+        let group = Arc::new(group);
+
+        let mut token = group.task_token();
+        let (release, handle) = hold(&group, &mutex);
+
+        let waker = Waker::from(Arc::new(CountingWaker::default()));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = (mutex.read(),).lock_async(&mut token);
+        for _ in 0..3 {
+            assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        }
+
+        release.send(()).unwrap();
+        handle.join().unwrap();
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+        drop(future);
+
+        let stats = &group.stats()[&mutex.lock_id()];
+        assert_eq!(stats.acquisitions, 2);
+        assert_eq!(stats.contentions, 1);
+        assert!(stats.total_wait > std::time::Duration::from_secs(0));
+    }
+
+    #[test]
+    #[cfg(feature = "poison")]
+    fn poisoned() {
+        let mut group = LockGroup::new();
+        let mutex = Arc::new(group.mutex(1));
+        let group = Arc::new(group);
+
+        {
+            let group = group.clone();
+            let mutex = mutex.clone();
+
+            let _ = thread::spawn(move || {
+                let mut token = group.token();
+                let _guard = (mutex.write(),).lock(&mut token);
+
+                panic!("Failed while holding the lock");
+            })
+            .join();
+        }
+
+        let mut token = group.task_token();
+        let err = block_on((mutex.read(),).lock_async(&mut token)).unwrap_err();
+
+        assert_eq!(*err.into_inner().0, 1);
+    }
+
+    #[test]
+    fn pending_holds_nothing() {
+        let mut group = LockGroup::new();
+        let mutex_a = Arc::new(group.mutex(1));
+        let mutex_b = Arc::new(group.mutex(2));
+        let group = Arc::new(group);
+
+        let mut token = group.task_token();
+        let (release, handle) = hold(&group, &mutex_b);
+
+        let waker = Waker::from(Arc::new(CountingWaker::default()));
+        let mut cx = Context::from_waker(&waker);
+
+        let mut future = lock2_async(&mut token, mutex_a.read(), mutex_b.read());
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+        // `mutex_a` has been released again
+        let mut thread_token = group.token();
+        assert_eq!(*(mutex_a.read(),).lock(&mut thread_token).unwrap().0, 1);
+        drop(thread_token);
+
+        release.send(()).unwrap();
+        handle.join().unwrap();
+
+        let (a, b) = block_on(future).unwrap();
+        assert_eq!(*a + *b, 3);
+    }
+
+    #[test]
+    fn concurrent_tasks() {
+        fn assert_send<T: Send>(_: &T) {}
+
+        let mut group = LockGroup::new();
+        let mutex_a = group.mutex(1);
+        let mutex_b = group.mutex(2);
+
+        let mut token_a = group.task_token();
+        let mut token_b = group.task_token();
+        assert_send(&token_a);
+
+        let future = lock2_async(&mut token_b, mutex_a.write(), mutex_b.write());
+        assert_send(&future);
+        drop(future);
+
+        let first = async {
+            let (mut b,) = (mutex_b.write(),).lock_async(&mut token_a).await.unwrap();
+            // the second task runs while this one holds `mutex_b`
+            YieldNow(false).await;
+            *b *= 10;
+        };
+        let second = async {
+            let (mut a, b) = lock2_async(&mut token_b, mutex_a.write(), mutex_b.read())
+                .await
+                .unwrap();
+            *a += *b;
+        };
+        block_on_all(vec![Box::pin(first), Box::pin(second)]);
+
+        let mut token = group.token();
+        let (a, b) = lock2(&mut token, mutex_a.read(), mutex_b.read()).unwrap();
+        assert_eq!((*a, *b), (21, 20));
+    }
+
+    #[test]
+    fn block_on_contended() {
+        let mut group = LockGroup::new();
+        let mutex = Arc::new(group.mutex(0));
+        let group = Arc::new(group);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let group = group.clone();
+                let mutex = mutex.clone();
+
+                thread::spawn(move || {
+                    let mut token = group.task_token();
+
+                    for _ in 0..1000 {
+                        let (mut guard,) =
+                            block_on((mutex.write(),).lock_async(&mut token)).unwrap();
+                        *guard += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut token = group.token();
+        assert_eq!(*(mutex.read(),).lock(&mut token).unwrap().0, 4000);
+    }
+}
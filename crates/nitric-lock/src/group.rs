@@ -1,36 +1,365 @@
-use crate::{mutex::new_mutex, Mutex};
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "stats")]
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+
+use lock_api::{RawMutex, RawRwLockUpgrade};
+
+#[cfg(feature = "stats")]
+use crate::stats::{LockStats, Stats};
+use crate::{mutex::new_mutex, rwlock::new_rw_lock, stats::SharedStats, LockId, Mutex, RwLock};
+
+static NEXT_GROUP_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The minimum length of the statistics list at which it is pruned.
+#[cfg(feature = "stats")]
+const MIN_PRUNE_AT: usize = 16;
+
+thread_local! {
+    static TOKEN_ALIVE: Cell<bool> = const { Cell::new(false) };
+    static HELD_GUARDS: RefCell<Vec<LockId>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Must be called whenever a lock guard is created.
+pub(crate) fn guard_created(id: LockId) {
+    HELD_GUARDS.with(|held| held.borrow_mut().push(id));
+}
+
+/// Must be called whenever a lock guard is dropped.
+pub(crate) fn guard_dropped(id: LockId) {
+    HELD_GUARDS.with(|held| {
+        let mut held = held.borrow_mut();
+
+        if let Some(pos) = held.iter().rposition(|h| *h == id) {
+            held.swap_remove(pos);
+        }
+    });
+}
+
+/// Returns the number of lock guards alive on the current thread.
+pub(crate) fn held_guards() -> usize {
+    HELD_GUARDS.with(|held| held.borrow().len())
+}
+
+/// Returns the highest ID of the locks held by the current thread.
+pub(crate) fn highest_held() -> Option<LockId> {
+    HELD_GUARDS.with(|held| held.borrow().iter().max().cloned())
+}
+
+/// Returns the IDs of the locks held by the current thread.
+#[cfg(feature = "deadlock-detection")]
+pub(crate) fn held_locks() -> Vec<LockId> {
+    HELD_GUARDS.with(|held| held.borrow().clone())
+}
 
-#[derive(Default)]
 pub struct LockGroup {
     counter: usize,
-    unique_id: Box<u8>,
+    id: usize,
+    #[cfg(feature = "stats")]
+    stats: Vec<(LockId, Option<String>, Weak<Stats>)>,
+    /// The length of `stats` at which dropped locks are pruned next.
+    #[cfg(feature = "stats")]
+    prune_at: usize,
 }
 
 impl LockGroup {
     pub fn new() -> Self {
-        Default::default()
+        let id = NEXT_GROUP_ID.fetch_add(1, Ordering::Relaxed);
+        assert_ne!(
+            id,
+            usize::MAX,
+            "Allocated more than `usize::MAX` lock groups"
+        );
+
+        LockGroup {
+            counter: 0,
+            id,
+            #[cfg(feature = "stats")]
+            stats: Vec::new(),
+            #[cfg(feature = "stats")]
+            prune_at: MIN_PRUNE_AT,
+        }
     }
 
+    /// Returns the ID of this group, which is unique for the whole process.
+    ///
+    /// Locks of groups with a lower ID are always acquired first.
     pub fn id(&self) -> usize {
-        self.unique_id.as_ref() as *const _ as usize
+        self.id
     }
 
     pub fn mutex<T>(&mut self, value: T) -> Mutex<T> {
-        let r = new_mutex(value, self.counter);
+        let (id, stats) = self.allocate(None);
+
+        new_mutex(value, id, stats)
+    }
+
+    /// Like `mutex`, but uses `R` as raw lock instead of `parking_lot`'s
+    /// default. See the `raw` module for the backends provided by this crate.
+    pub fn mutex_with<R: RawMutex, T>(&mut self, value: T) -> Mutex<T, R> {
+        let (id, stats) = self.allocate(None);
+
+        new_mutex(value, id, stats)
+    }
+
+    /// Creates a reader-writer lock, which can be acquired by many readers at
+    /// once, or in upgradable mode.
+    pub fn rw_lock<T>(&mut self, value: T) -> RwLock<T> {
+        let (id, stats) = self.allocate(None);
+
+        new_rw_lock(value, id, stats)
+    }
+
+    /// Like `rw_lock`, but uses `R` as raw lock instead of `parking_lot`'s
+    /// default.
+    pub fn rw_lock_with<R: RawRwLockUpgrade, T>(&mut self, value: T) -> RwLock<T, R> {
+        let (id, stats) = self.allocate(None);
+
+        new_rw_lock(value, id, stats)
+    }
+
+    /// Like `mutex`, but gives the lock a name which shows up in the
+    /// statistics of this group. The name is discarded if the `stats`
+    /// feature is disabled.
+    pub fn named_mutex<T>(&mut self, name: impl Into<String>, value: T) -> Mutex<T> {
+        let (id, stats) = self.allocate(Some(name.into()));
+
+        new_mutex(value, id, stats)
+    }
+
+    /// Returns a snapshot of the statistics of all locks of this group that
+    /// are still alive, keyed by their ID.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> BTreeMap<LockId, LockStats> {
+        self.stats
+            .iter()
+            .filter_map(|(id, name, stats)| {
+                stats
+                    .upgrade()
+                    .map(|stats| (*id, stats.snapshot(*id, name.clone())))
+            })
+            .collect()
+    }
+
+    #[allow(unused_variables)]
+    fn allocate(&mut self, name: Option<String>) -> (LockId, SharedStats) {
+        let id = LockId::new(self.id, self.counter);
 
         self.counter = self
             .counter
             .checked_add(1)
             .expect("Allocated more than `usize::MAX` locks");
 
-        r
+        #[cfg(feature = "stats")]
+        {
+            // Pruning only once the list has doubled keeps allocating
+            // amortized constant time.
+            if self.stats.len() >= self.prune_at {
+                self.stats.retain(|(_, _, stats)| stats.strong_count() > 0);
+                self.prune_at = (2 * self.stats.len()).max(MIN_PRUNE_AT);
+            }
+
+            let stats = Arc::new(Stats::new());
+            self.stats.push((id, name, Arc::downgrade(&stats)));
+
+            (id, stats)
+        }
+
+        #[cfg(not(feature = "stats"))]
+        (id, SharedStats::new())
+    }
+
+    /// Creates the lock token for the current thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there already is a live `LockToken` on this thread. Use
+    /// `try_token` for a non-panicking version.
+    pub fn token(&self) -> LockToken {
+        self.try_token()
+            .expect("There can only be one `LockToken` per thread at a time")
+    }
+
+    /// Creates the lock token for the current thread, returning `None` if
+    /// there already is a live `LockToken` on this thread.
+    pub fn try_token(&self) -> Option<LockToken> {
+        TOKEN_ALIVE.with(|alive| match alive.replace(true) {
+            true => None,
+            false => Some(LockToken {
+                marker: PhantomData,
+            }),
+        })
     }
 
-    pub fn token(&mut self) -> LockToken {
-        LockToken { _opaque: () }
+    /// Creates a token for an asynchronous task. Unlike `token`, this can be
+    /// called any number of times, on any thread.
+    pub fn task_token(&self) -> TaskToken {
+        TaskToken { _private: () }
     }
 }
 
+impl Default for LockGroup {
+    fn default() -> Self {
+        LockGroup::new()
+    }
+}
+
+/// A token that is required for acquiring locks.
+///
+/// There can only be one `LockToken` per thread at a time, and all guards
+/// returned by the joined locking functions borrow the token mutably. This
+/// makes it impossible to acquire further locks while still holding some,
+/// which is what could otherwise result in a deadlock.
+///
+/// A token is bound to the thread it was created on:
+///
+/// ```compile_fail,E0277
+/// use nitric_lock::LockGroup;
+///
+/// let group = LockGroup::new();
+/// let token = group.token();
+///
+/// std::thread::spawn(move || drop(token));
+/// ```
+///
+/// Locks cannot be acquired again before the guards have been released:
+///
+/// ```compile_fail,E0499
+/// use nitric_lock::{lock2, LockGroup, ReadLock, WriteLock};
+///
+/// let mut group = LockGroup::new();
+/// let mut token = group.token();
+///
+/// let a = group.mutex(1);
+/// let b = group.mutex(2);
+/// let c = group.mutex(3);
+/// let d = group.mutex(4);
+///
+/// let (a, b) = lock2(&mut token, a.read(), b.write()).unwrap();
+/// let (c, d) = lock2(&mut token, c.read(), d.read()).unwrap();
+///
+/// drop((a, b));
+/// ```
+///
+/// Once they are dropped, the token can be used again:
+///
+/// ```
+/// use nitric_lock::{lock2, LockGroup, ReadLock, WriteLock};
+///
+/// let mut group = LockGroup::new();
+/// let mut token = group.token();
+///
+/// let a = group.mutex(1);
+/// let b = group.mutex(2);
+///
+/// {
+///     let (a, mut b) = lock2(&mut token, a.read(), b.write()).unwrap();
+///     *b += *a;
+/// }
+///
+/// let (a, b) = lock2(&mut token, a.read(), b.read()).unwrap();
+/// assert_eq!(*a + *b, 4);
+/// ```
 pub struct LockToken {
-    _opaque: (),
+    marker: PhantomData<*mut ()>,
+}
+
+impl Drop for LockToken {
+    fn drop(&mut self) {
+        TOKEN_ALIVE.with(|alive| alive.set(false));
+    }
+}
+
+/// A token that is required for acquiring locks asynchronously, with
+/// `LockSet::lock_async`.
+///
+/// Like a `LockToken`, it is borrowed mutably by the future and the guards it
+/// resolves to, so a task cannot acquire further locks while still holding
+/// some. Unlike a `LockToken`, it is `Send` and not bound to a thread, so
+/// many tasks can lock concurrently on the same executor thread.
+///
+/// Every task must use a single `TaskToken` only; a task holding the guards
+/// of one token while waiting for a future of another one can deadlock. For
+/// the same reason, the guards of a `LockToken` must not be held while
+/// blocking on a `LockFuture`.
+///
+/// ```
+/// use std::{future::Future, sync::Arc};
+///
+/// use nitric_lock::{LockGroup, LockSet, WriteLock};
+///
+/// fn spawn<F: Future + Send + 'static>(_: F) {}
+///
+/// let mut group = LockGroup::new();
+/// let mutex = Arc::new(group.mutex(0));
+/// let mut token = group.task_token();
+///
+/// spawn(async move {
+///     let (mut guard,) = (mutex.write(),).lock_async(&mut token).await.unwrap();
+///     *guard += 1;
+/// });
+/// ```
+pub struct TaskToken {
+    _private: (),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_per_thread() {
+        let group = LockGroup::new();
+
+        let token = group.token();
+        assert!(group.try_token().is_none());
+        assert!(LockGroup::new().try_token().is_none());
+
+        std::thread::spawn(|| assert!(LockGroup::new().try_token().is_some()))
+            .join()
+            .unwrap();
+
+        drop(token);
+        assert!(group.try_token().is_some());
+    }
+
+    #[test]
+    fn unique_ids() {
+        let mut a = LockGroup::new();
+        let mut b = LockGroup::new();
+
+        assert_ne!(a.id(), b.id());
+        assert_ne!(a.mutex(()).lock_id(), b.mutex(()).lock_id());
+        assert!(a.mutex(()).lock_id() < a.mutex(()).lock_id());
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn prune_stats() {
+        let mut group = LockGroup::new();
+        let alive: Vec<_> = (0..20).map(|i| group.mutex(i)).collect();
+
+        for _ in 0..1000 {
+            group.mutex(());
+        }
+
+        assert!(group.stats.len() <= 2 * alive.len() + MIN_PRUNE_AT);
+        assert_eq!(group.stats().len(), alive.len());
+    }
+
+    #[test]
+    #[should_panic]
+    fn second_token_panics() {
+        let group = LockGroup::new();
+
+        let _a = group.token();
+        let _b = group.token();
+    }
 }
@@ -0,0 +1,35 @@
+use std::fmt::{self, Display, Formatter};
+
+/// A globally unique lock ID, defining the order in which locks are acquired.
+///
+/// Lock IDs are ordered by the ID of the `LockGroup` the lock was allocated
+/// from first, and by the index of the lock inside that group second. This
+/// results in a total order for all locks, no matter which group they belong
+/// to.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LockId {
+    group: usize,
+    index: usize,
+}
+
+impl LockId {
+    pub(crate) fn new(group: usize, index: usize) -> Self {
+        LockId { group, index }
+    }
+
+    /// The ID of the `LockGroup` this lock was allocated from.
+    pub fn group(&self) -> usize {
+        self.group
+    }
+
+    /// The index of this lock inside its group.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl Display for LockId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.group, self.index)
+    }
+}
@@ -1,35 +1,212 @@
 //! Joined locking
 //!
 
-use crate::{Lock, LockToken};
+use crate::{Lock, LockFuture, LockInfo, LockResult, LockToken, PoisonError, TaskToken};
 
-pub fn lock2<'token, A, B>(_: &'token mut LockToken, a: A, b: B) -> (A::Output, B::Output)
+/// A set of locks that can be acquired at once, implemented for tuples of
+/// `Lock`s.
+///
+/// The locks are always acquired in the order defined by their `LockId`s, no
+/// matter in which order they appear in the tuple.
+pub trait LockSet<'token>: Sized {
+    /// The guards that are returned once all locks are acquired.
+    type Output;
+
+    /// Acquires all locks of this set, blocking the current thread until all
+    /// of them are available.
+    ///
+    /// # Errors
+    ///
+    /// Like `std::sync::Mutex::lock`, returns a `PoisonError` carrying the
+    /// guards if one of the locks is poisoned. Without the `poison` feature,
+    /// this never fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same lock is contained twice.
+    fn lock(self, _: &'token mut LockToken) -> LockResult<Self::Output> {
+        unsafe {
+            let mut locks = self.lock_infos();
+            sort_locks(&mut locks);
+
+            acquire(&locks);
+
+            let poisoned = is_poisoned(&locks);
+            drop(locks);
+
+            poison_result(self.lock_unchecked(), poisoned)
+        }
+    }
+
+    /// Returns a future that resolves once all locks of this set are acquired.
+    ///
+    /// Instead of blocking, the future registers its task's waker with the
+    /// lock it is waiting for. It either acquires all locks at once or none
+    /// of them, so it holds no locks while waiting.
+    ///
+    /// The future borrows the `TaskToken` of its task and is `Send`, but the
+    /// guards are not, see `LockFuture` for details.
+    ///
+    /// The future resolves to the same `LockResult` as `lock`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the same lock is contained twice.
+    fn lock_async(self, _: &'token mut TaskToken) -> LockFuture<'token, Self> {
+        LockFuture::new(self)
+    }
+
+    /// Returns the lock information of all locks of this set, in the order of
+    /// the tuple.
+    ///
+    /// # Safety
+    ///
+    /// The raw locks must only be acquired in the order of their IDs.
+    unsafe fn lock_infos(&self) -> Vec<LockInfo<'token>>;
+
+    /// Creates the guards of all locks.
+    ///
+    /// # Safety
+    ///
+    /// All locks of this set must have been acquired already.
+    unsafe fn lock_unchecked(self) -> Self::Output;
+}
+
+macro_rules! impl_lock_set {
+    ($($ty:ident $idx:tt),+) => {
+        impl<'token, $($ty),+> LockSet<'token> for ($($ty,)+)
+        where
+            $($ty: Lock<'token> + 'token,)+
+        {
+            type Output = ($($ty::Output,)+);
+
+            unsafe fn lock_infos(&self) -> Vec<LockInfo<'token>> {
+                vec![$(self.$idx.lock_info()),+]
+            }
+
+            unsafe fn lock_unchecked(self) -> Self::Output {
+                ($(self.$idx.lock_unchecked(),)+)
+            }
+        }
+    };
+}
+
+impl_lock_set!(A 0);
+impl_lock_set!(A 0, B 1);
+impl_lock_set!(A 0, B 1, C 2);
+impl_lock_set!(A 0, B 1, C 2, D 3);
+impl_lock_set!(A 0, B 1, C 2, D 3, E 4);
+impl_lock_set!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_lock_set!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_lock_set!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Acquires the locks `a` and `b`, in the order of their IDs.
+///
+/// # Errors
+///
+/// Returns a `PoisonError` carrying the guards if one of the locks is
+/// poisoned, see `LockSet::lock`.
+///
+/// # Panics
+///
+/// Panics if `a` and `b` refer to the same lock.
+pub fn lock2<'token, A, B>(
+    token: &'token mut LockToken,
+    a: A,
+    b: B,
+) -> LockResult<(A::Output, B::Output)>
+where
+    A: Lock<'token> + 'token,
+    B: Lock<'token> + 'token,
+{
+    (a, b).lock(token)
+}
+
+/// Returns a future resolving once the locks `a` and `b` are acquired. See
+/// `LockSet::lock_async`.
+pub fn lock2_async<'token, A, B>(
+    token: &'token mut TaskToken,
+    a: A,
+    b: B,
+) -> LockFuture<'token, (A, B)>
 where
     A: Lock<'token> + 'token,
     B: Lock<'token> + 'token,
 {
-    unsafe {
-        let lock_a = a.lock_info();
-        let lock_b = b.lock_info();
+    (a, b).lock_async(token)
+}
 
-        let mut locks = [&lock_a, &lock_b];
+pub(crate) fn is_poisoned(locks: &[LockInfo<'_>]) -> bool {
+    locks.iter().any(|info| info.poison.get())
+}
 
-        if locks[0].id > locks[1].id {
-            let tmp = locks[0];
+pub(crate) fn poison_result<G>(guards: G, poisoned: bool) -> LockResult<G> {
+    match poisoned {
+        true => Err(PoisonError::new(guards)),
+        false => Ok(guards),
+    }
+}
 
-            locks[0] = locks[1];
-            locks[1] = tmp;
-        }
+/// Acquires the sorted `locks`, blocking until all of them are available.
+///
+/// The deadlock detector checks the whole set before anything is locked, so
+/// a report never leaves locks behind that have no guard to release them.
+pub(crate) fn acquire(locks: &[LockInfo<'_>]) {
+    #[cfg(feature = "deadlock-detection")]
+    for info in locks {
+        crate::deadlock::lock_requested(info.id);
+    }
 
-        locks[0].guard.lock();
-        locks[1].guard.lock();
+    for info in locks {
+        info.stats.acquire(&info.guard);
 
-        (a.lock_unchecked(), b.lock_unchecked())
+        #[cfg(feature = "deadlock-detection")]
+        crate::deadlock::lock_acquired(info.id);
     }
 }
 
-// TODO: add more `joinN` functions
-// TODO: add macro
+/// Sorts `locks` by their ID, which is the order they have to be acquired in.
+///
+/// # Panics
+///
+/// Panics if the same lock is contained twice, or if two distinct locks share
+/// the same ID.
+pub(crate) fn sort_locks(locks: &mut [LockInfo<'_>]) {
+    locks.sort_unstable_by_key(|info| info.id);
+
+    for pair in locks.windows(2) {
+        if pair[0].id == pair[1].id {
+            match pair[0].guard.is_same(&pair[1].guard) {
+                true => panic!("Attempted to acquire lock {} twice", pair[0].id),
+                false => panic!("Two distinct locks share the ID {}", pair[0].id),
+            }
+        }
+    }
+}
+
+/// Acquires all given locks with the given token, in the order of their IDs.
+///
+/// Expands to `LockSet::lock` on a tuple of the locks, so it returns a
+/// `LockResult` of a tuple of the guards, in the order the locks are passed.
+///
+/// ```
+/// use nitric_lock::{lock, LockGroup, ReadLock, WriteLock};
+///
+/// let mut group = LockGroup::new();
+/// let mut token = group.token();
+///
+/// let a = group.mutex(1);
+/// let b = group.mutex(2);
+///
+/// let (a, mut b) = lock!(&mut token, a.read(), b.write()).unwrap();
+/// *b += *a;
+/// ```
+#[macro_export]
+macro_rules! lock {
+    ($token:expr, $($lock:expr),+ $(,)?) => {
+        $crate::LockSet::lock(($($lock,)+), $token)
+    };
+}
 
 #[cfg(test)]
 mod tests {
@@ -44,7 +221,7 @@ mod tests {
         let mutex_a = group.mutex(42);
         let mutex_b = group.mutex(35);
 
-        let (a, mut b) = lock2(&mut token, mutex_a.read(), mutex_b.write());
+        let (a, mut b) = lock2(&mut token, mutex_a.read(), mutex_b.write()).unwrap();
 
         assert_eq!(*a, 42);
         assert_eq!(*b, 35);
@@ -60,11 +237,112 @@ mod tests {
         let mutex_b = group.mutex(35);
         let mutex_a = group.mutex(42);
 
-        let (a, mut b) = lock2(&mut token, mutex_a.read(), mutex_b.write());
+        let (a, mut b) = lock2(&mut token, mutex_a.read(), mutex_b.write()).unwrap();
 
         assert_eq!(*a, 42);
         assert_eq!(*b, 35);
 
         *b = 15;
     }
+
+    #[test]
+    fn test_lock2_groups() {
+        let mut group_a = LockGroup::new();
+        let mut group_b = LockGroup::new();
+        let mut token = group_a.token();
+
+        let mutex_b = group_b.mutex(35);
+        let mutex_a = group_a.mutex(42);
+
+        assert_eq!(mutex_a.lock_id().index(), mutex_b.lock_id().index());
+
+        {
+            let (a, b) = lock2(&mut token, mutex_a.read(), mutex_b.read()).unwrap();
+
+            assert_eq!(*a, 42);
+            assert_eq!(*b, 35);
+        }
+
+        let (b, a) = lock2(&mut token, mutex_b.read(), mutex_a.read()).unwrap();
+
+        assert_eq!(*a, 42);
+        assert_eq!(*b, 35);
+    }
+
+    #[test]
+    #[should_panic(expected = "twice")]
+    fn test_lock2_same() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex = group.mutex(42);
+
+        let _ = lock2(&mut token, mutex.read(), mutex.read()).unwrap();
+    }
+
+    #[test]
+    fn test_lock_set() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex_c = group.mutex(3);
+        let mutex_a = group.mutex(1);
+        let mutex_b = group.mutex(2);
+
+        let (a, b, mut c) = (mutex_a.read(), mutex_b.read(), mutex_c.write())
+            .lock(&mut token)
+            .unwrap();
+        *c += *a + *b;
+        drop((a, b, c));
+
+        let (c,) = (mutex_c.read(),).lock(&mut token).unwrap();
+        assert_eq!(*c, 6);
+    }
+
+    #[test]
+    fn test_lock2_threads() {
+        use std::{sync::Arc, thread};
+
+        let mut group = LockGroup::new();
+        let mut other = LockGroup::new();
+
+        let mutexes = Arc::new((other.mutex(0), group.mutex(0)));
+        let group = Arc::new(group);
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let group = group.clone();
+                let mutexes = mutexes.clone();
+
+                thread::spawn(move || {
+                    let mut token = group.token();
+                    let (a, b) = &*mutexes;
+
+                    for _ in 0..1000 {
+                        let (mut a, mut b) = match i % 2 {
+                            0 => lock2(&mut token, a.write(), b.write()).unwrap(),
+                            _ => {
+                                let (b, a) = lock2(&mut token, b.write(), a.write()).unwrap();
+
+                                (a, b)
+                            }
+                        };
+
+                        *a += 1;
+                        *b += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut token = group.token();
+        let (a, b) = lock2(&mut token, mutexes.0.read(), mutexes.1.read()).unwrap();
+
+        assert_eq!(*a, 4000);
+        assert_eq!(*b, 4000);
+    }
 }
@@ -1,18 +1,49 @@
 #![deny(unused_must_use)]
 
 //! # `nitric-lock`
+//!
+//! ## Features
+//!
+//! * `deadlock-detection`: Panics with a report if locks are acquired in an
+//!   order that could result in a deadlock. See the `deadlock` module.
+//! * `poison`: Marks locks as poisoned if a thread panics while holding them.
+//!   See the `poison` module.
+//! * `stats`: Records contention and hold-time statistics for every lock. See
+//!   the `stats` module.
 
 pub use self::{
-    group::{LockGroup, LockToken},
-    join::lock2,
-    lock::{Lock, LockInfo, Mut, RawLockGuard, ReadLock, Ref, WriteLock},
+    condvar::{Condvar, WaitTimeoutResult},
+    future::LockFuture,
+    group::{LockGroup, LockToken, TaskToken},
+    id::LockId,
+    join::{lock2, lock2_async, LockSet},
+    lock::{
+        Lock, LockInfo, Mut, RawLockGuard, ReadLock, Ref, Upgradable, UpgradableLock, WriteLock,
+    },
     mutex::{Mutex, MutexGuard},
+    poison::{LockResult, PoisonError},
+    rwlock::{RwLock, RwLockReadGuard, RwLockUpgradableReadGuard, RwLockWriteGuard},
+    waiters::Waiters,
 };
 
+#[cfg(feature = "deadlock-detection")]
+pub mod deadlock;
+
+mod condvar;
+mod future;
 mod group;
+mod id;
 mod join;
 mod lock;
 mod mutex;
+pub mod poison;
+pub mod raw;
+mod rwlock;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(not(feature = "stats"))]
+mod stats;
+mod waiters;
 
 // TODO: remove this code once the `join` mod is done
 /*
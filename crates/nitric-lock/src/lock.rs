@@ -1,22 +1,44 @@
-use lock_api::RawMutex as Unused0;
-use parking_lot::RawMutex;
-
+use crate::{
+    poison::Poison,
+    raw::{DynRawMutex, DynRawRwLock},
+    stats::Stats,
+    LockId, Waiters,
+};
+
+/// A lock that can be acquired as part of a `LockSet`, usually created with
+/// `ReadLock::read` or `WriteLock::write`.
 pub trait Lock<'a> {
     type Output;
 
-    unsafe fn lock_info(&self) -> LockInfo<'_>;
+    /// Returns the information required for acquiring the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must only be acquired in the order of the lock IDs.
+    unsafe fn lock_info(&self) -> LockInfo<'a>;
+    /// Creates the guard of this lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must have been acquired already.
     unsafe fn lock_unchecked(self) -> Self::Output;
 }
 
 pub struct LockInfo<'a> {
-    pub id: usize,
+    pub id: LockId,
     pub guard: RawLockGuard<'a>,
+    pub waiters: &'a Waiters,
+    pub poison: &'a Poison,
+    pub stats: &'a Stats,
 }
 
 pub enum Never {}
 
 pub enum RawLockGuard<'a> {
-    RawMutex(&'a RawMutex),
+    RawMutex(&'a dyn DynRawMutex),
+    RawRwLockShared(&'a dyn DynRawRwLock),
+    RawRwLockExclusive(&'a dyn DynRawRwLock),
+    RawRwLockUpgradable(&'a dyn DynRawRwLock),
 
     #[doc(hidden)]
     __NonExhaustive(Never),
@@ -25,14 +47,50 @@ pub enum RawLockGuard<'a> {
 impl<'a> RawLockGuard<'a> {
     pub fn lock(&self) {
         match *self {
-            RawLockGuard::RawMutex(ref raw) => raw.lock(),
+            RawLockGuard::RawMutex(raw) => raw.lock(),
+            RawLockGuard::RawRwLockShared(raw) => raw.lock_shared(),
+            RawLockGuard::RawRwLockExclusive(raw) => raw.lock_exclusive(),
+            RawLockGuard::RawRwLockUpgradable(raw) => raw.lock_upgradable(),
             RawLockGuard::__NonExhaustive(ref n) => match *n {},
         }
     }
 
     pub fn try_lock(&self) -> bool {
         match *self {
-            RawLockGuard::RawMutex(ref raw) => raw.try_lock(),
+            RawLockGuard::RawMutex(raw) => raw.try_lock(),
+            RawLockGuard::RawRwLockShared(raw) => raw.try_lock_shared(),
+            RawLockGuard::RawRwLockExclusive(raw) => raw.try_lock_exclusive(),
+            RawLockGuard::RawRwLockUpgradable(raw) => raw.try_lock_upgradable(),
+            RawLockGuard::__NonExhaustive(ref n) => match *n {},
+        }
+    }
+
+    /// Unlocks the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The lock must be held by the current context.
+    pub unsafe fn unlock(&self) {
+        match *self {
+            RawLockGuard::RawMutex(raw) => raw.unlock(),
+            RawLockGuard::RawRwLockShared(raw) => raw.unlock_shared(),
+            RawLockGuard::RawRwLockExclusive(raw) => raw.unlock_exclusive(),
+            RawLockGuard::RawRwLockUpgradable(raw) => raw.unlock_upgradable(),
+            RawLockGuard::__NonExhaustive(ref n) => match *n {},
+        }
+    }
+
+    /// Checks if both guards refer to the same raw lock.
+    pub fn is_same(&self, other: &RawLockGuard<'_>) -> bool {
+        self.addr() == other.addr()
+    }
+
+    fn addr(&self) -> *const () {
+        match *self {
+            RawLockGuard::RawMutex(raw) => raw as *const dyn DynRawMutex as *const (),
+            RawLockGuard::RawRwLockShared(raw)
+            | RawLockGuard::RawRwLockExclusive(raw)
+            | RawLockGuard::RawRwLockUpgradable(raw) => raw as *const dyn DynRawRwLock as *const (),
             RawLockGuard::__NonExhaustive(ref n) => match *n {},
         }
     }
@@ -46,7 +104,7 @@ where
 {
     type Output = <T as WriteLock<'a>>::Output;
 
-    unsafe fn lock_info(&self) -> LockInfo<'_> {
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
         <T as WriteLock<'a>>::lock_info(&self.0)
     }
 
@@ -63,7 +121,25 @@ where
 {
     type Output = T::Output;
 
-    unsafe fn lock_info(&self) -> LockInfo<'_> {
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        self.0.lock_info()
+    }
+
+    unsafe fn lock_unchecked(self) -> Self::Output {
+        self.0.lock_unchecked()
+    }
+}
+
+/// Wrapper returned by `UpgradableLock::upgradable`.
+pub struct Upgradable<T>(T);
+
+impl<'a, T> Lock<'a> for Upgradable<T>
+where
+    T: UpgradableLock<'a>,
+{
+    type Output = T::Output;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
         self.0.lock_info()
     }
 
@@ -82,7 +158,17 @@ pub trait ReadLock<'a> {
         Ref(self)
     }
 
-    unsafe fn lock_info(&self) -> LockInfo<'_>;
+    /// Returns the information required for acquiring the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must only be acquired in the order of the lock IDs.
+    unsafe fn lock_info(&self) -> LockInfo<'a>;
+    /// Creates the guard of this lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must have been acquired already.
     unsafe fn lock_unchecked(self) -> Self::Output;
 }
 
@@ -96,6 +182,43 @@ pub trait WriteLock<'a> {
         Mut(self)
     }
 
-    unsafe fn lock_info(&self) -> LockInfo<'_>;
+    /// Returns the information required for acquiring the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must only be acquired in the order of the lock IDs.
+    unsafe fn lock_info(&self) -> LockInfo<'a>;
+    /// Creates the guard of this lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must have been acquired already.
     unsafe fn lock_unchecked(self) -> <Self as WriteLock<'a>>::Output;
 }
+
+/// A lock that can be acquired in upgradable mode: shared with readers, but
+/// exclusive with other upgradable and write locks. The guard can later be
+/// upgraded to a write guard.
+pub trait UpgradableLock<'a> {
+    type Output;
+
+    fn upgradable(self) -> Upgradable<Self>
+    where
+        Self: Sized,
+    {
+        Upgradable(self)
+    }
+
+    /// Returns the information required for acquiring the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must only be acquired in the order of the lock IDs.
+    unsafe fn lock_info(&self) -> LockInfo<'a>;
+    /// Creates the guard of this lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must have been acquired already.
+    unsafe fn lock_unchecked(self) -> <Self as UpgradableLock<'a>>::Output;
+}
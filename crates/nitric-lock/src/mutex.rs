@@ -1,33 +1,68 @@
 use std::{
     cell::UnsafeCell,
+    fmt::{self, Debug, Formatter},
     marker::PhantomData,
     ops::{Deref, DerefMut},
 };
 
-use lock_api::RawMutex as Unused0;
-use parking_lot::RawMutex;
+use lock_api::RawMutex;
 
-use crate::{LockInfo, RawLockGuard, ReadLock, WriteLock};
+use crate::{
+    group::{guard_created, guard_dropped},
+    poison::{Poison, PoisonGuard},
+    stats::{SharedStats, StatsGuard},
+    LockId, LockInfo, RawLockGuard, ReadLock, Waiters, WriteLock,
+};
 
-pub fn new_mutex<T>(data: T, id: usize) -> Mutex<T> {
+pub fn new_mutex<T, R: RawMutex>(data: T, id: LockId, stats: SharedStats) -> Mutex<T, R> {
     Mutex {
         data: UnsafeCell::new(data),
         id,
-        raw: RawMutex::INIT,
+        raw: R::INIT,
+        waiters: Waiters::new(),
+        poison: Poison::new(),
+        stats,
+        #[cfg(feature = "deadlock-detection")]
+        _registration: crate::deadlock::Registration(id),
     }
 }
 
-pub struct Mutex<T> {
+/// A mutual exclusion lock, allocated from a `LockGroup`.
+///
+/// The raw lock used for blocking is determined by `R`, which can be any
+/// `lock_api::RawMutex`. Besides `parking_lot`'s default, this crate provides
+/// some more in the `raw` module.
+pub struct Mutex<T, R = parking_lot::RawMutex> {
     data: UnsafeCell<T>,
-    id: usize,
-    raw: RawMutex,
+    id: LockId,
+    raw: R,
+    waiters: Waiters,
+    poison: Poison,
+    stats: SharedStats,
+    /// Removes the lock from the deadlock detector when dropped.
+    #[cfg(feature = "deadlock-detection")]
+    _registration: crate::deadlock::Registration,
 }
 
-impl<T> Mutex<T> {
-    pub fn lock_id(&self) -> usize {
+unsafe impl<T: Send, R: RawMutex + Sync> Sync for Mutex<T, R> {}
+
+impl<T, R: RawMutex> Mutex<T, R> {
+    pub fn lock_id(&self) -> LockId {
         self.id
     }
 
+    /// Checks if this mutex is poisoned, which happens if a thread panicked
+    /// while holding it. Always `false` without the `poison` feature.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Clears the poisoned state of this mutex, marking the data as
+    /// recovered.
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
     // TODO: decide whether to expose them, and how
     // TODO: (simply exposing would easily allow deadlocks)
     /*
@@ -45,28 +80,48 @@ impl<T> Mutex<T> {
     }
     */
 
-    pub unsafe fn raw(&self) -> &RawMutex {
+    /// Returns the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must only be locked in the order of the lock IDs, and only
+    /// be unlocked if it has been locked before.
+    pub unsafe fn raw(&self) -> &R {
         &self.raw
     }
 
-    pub unsafe fn acquire_guard(&self) -> MutexGuard<'_, T> {
+    /// Creates a guard for this mutex.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must be locked by the current thread; the guard unlocks
+    /// it when dropped.
+    pub unsafe fn acquire_guard(&self) -> MutexGuard<'_, T, R> {
+        guard_created(self.id);
+
         MutexGuard {
             marker: PhantomData,
             mutex: self,
+            poison: self.poison.guard(),
+            stats: self.stats.guard(),
         }
     }
 }
 
-impl<'a, T> ReadLock<'a> for &'a Mutex<T>
+impl<'a, T, R> ReadLock<'a> for &'a Mutex<T, R>
 where
     T: 'a,
+    R: RawMutex + 'a,
 {
-    type Output = MutexGuard<'a, T>;
+    type Output = MutexGuard<'a, T, R>;
 
-    unsafe fn lock_info(&self) -> LockInfo<'_> {
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
         LockInfo {
             id: self.lock_id(),
             guard: RawLockGuard::RawMutex(self.raw()),
+            waiters: &self.waiters,
+            poison: &self.poison,
+            stats: &self.stats,
         }
     }
 
@@ -75,16 +130,20 @@ where
     }
 }
 
-impl<'a, T> WriteLock<'a> for &'a Mutex<T>
+impl<'a, T, R> WriteLock<'a> for &'a Mutex<T, R>
 where
     T: 'a,
+    R: RawMutex + 'a,
 {
-    type Output = MutexGuard<'a, T>;
+    type Output = MutexGuard<'a, T, R>;
 
-    unsafe fn lock_info(&self) -> LockInfo<'_> {
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
         LockInfo {
             id: self.lock_id(),
             guard: RawLockGuard::RawMutex(self.raw()),
+            waiters: &self.waiters,
+            poison: &self.poison,
+            stats: &self.stats,
         }
     }
 
@@ -93,12 +152,28 @@ where
     }
 }
 
-pub struct MutexGuard<'a, T> {
+pub struct MutexGuard<'a, T, R: RawMutex = parking_lot::RawMutex> {
     marker: PhantomData<(&'a mut T, *mut ())>,
-    mutex: &'a Mutex<T>,
+    mutex: &'a Mutex<T, R>,
+    poison: PoisonGuard,
+    stats: StatsGuard,
 }
 
-impl<T> Deref for MutexGuard<'_, T> {
+unsafe impl<T: Sync, R: RawMutex + Sync> Sync for MutexGuard<'_, T, R> {}
+
+impl<'a, T, R: RawMutex> MutexGuard<'a, T, R> {
+    pub(crate) fn mutex(guard: &Self) -> &'a Mutex<T, R> {
+        guard.mutex
+    }
+}
+
+impl<T: Debug, R: RawMutex> Debug for MutexGuard<'_, T, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, R: RawMutex> Deref for MutexGuard<'_, T, R> {
     type Target = T;
 
     fn deref(&self) -> &<Self as Deref>::Target {
@@ -106,14 +181,23 @@ impl<T> Deref for MutexGuard<'_, T> {
     }
 }
 
-impl<T> DerefMut for MutexGuard<'_, T> {
+impl<T, R: RawMutex> DerefMut for MutexGuard<'_, T, R> {
     fn deref_mut(&mut self) -> &mut <Self as Deref>::Target {
         unsafe { &mut *self.mutex.data.get() }
     }
 }
 
-impl<T> Drop for MutexGuard<'_, T> {
+impl<T, R: RawMutex> Drop for MutexGuard<'_, T, R> {
     fn drop(&mut self) {
+        self.mutex.poison.done(&self.poison);
+        self.mutex.stats.done(&self.stats);
+
+        #[cfg(feature = "deadlock-detection")]
+        crate::deadlock::lock_released(self.mutex.id);
+
         self.mutex.raw.unlock();
+        self.mutex.waiters.wake_all();
+
+        guard_dropped(self.mutex.id);
     }
 }
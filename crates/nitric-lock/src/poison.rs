@@ -0,0 +1,186 @@
+//! Lock poisoning
+//!
+//! With the `poison` feature enabled, a lock is marked as poisoned if a guard
+//! of it is dropped while the thread is panicking, matching the semantics of
+//! `std::sync::Mutex`. Without the feature, locks are never poisoned.
+
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+};
+
+#[cfg(feature = "poison")]
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+/// The result of a joined locking operation, which fails if one of the locks
+/// is poisoned.
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+/// The poison flag of a lock.
+#[derive(Debug, Default)]
+pub struct Poison {
+    #[cfg(feature = "poison")]
+    failed: AtomicBool,
+}
+
+impl Poison {
+    /// Creates a flag that is not poisoned.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Checks if the lock is poisoned.
+    #[inline]
+    pub fn get(&self) -> bool {
+        #[cfg(feature = "poison")]
+        return self.failed.load(Ordering::Relaxed);
+
+        #[cfg(not(feature = "poison"))]
+        false
+    }
+
+    /// Clears the poisoned state.
+    #[inline]
+    pub fn clear(&self) {
+        #[cfg(feature = "poison")]
+        self.failed.store(false, Ordering::Relaxed);
+    }
+
+    /// Must be called when a guard of the lock is created.
+    #[inline]
+    pub fn guard(&self) -> PoisonGuard {
+        PoisonGuard {
+            #[cfg(feature = "poison")]
+            panicking: thread::panicking(),
+        }
+    }
+
+    /// Must be called when a guard of the lock is dropped, before unlocking.
+    #[inline]
+    pub fn done(&self, guard: &PoisonGuard) {
+        #![allow(unused)]
+
+        #[cfg(feature = "poison")]
+        {
+            if !guard.panicking && thread::panicking() {
+                self.failed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Remembers whether the thread was panicking when a guard was created.
+#[derive(Debug)]
+pub struct PoisonGuard {
+    #[cfg(feature = "poison")]
+    panicking: bool,
+}
+
+/// Error returned if a lock was poisoned. It still carries the guards, which
+/// can be retrieved with `into_inner`.
+pub struct PoisonError<G> {
+    guards: G,
+}
+
+impl<G> PoisonError<G> {
+    /// Creates a poison error carrying `guards`.
+    pub fn new(guards: G) -> Self {
+        PoisonError { guards }
+    }
+
+    /// Consumes the error, returning the guards.
+    pub fn into_inner(self) -> G {
+        self.guards
+    }
+
+    /// Returns a reference to the guards.
+    pub fn get_ref(&self) -> &G {
+        &self.guards
+    }
+
+    /// Returns a mutable reference to the guards.
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guards
+    }
+}
+
+impl<G> Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> Display for PoisonError<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("poisoned lock: another task failed inside")
+    }
+}
+
+impl<G> Error for PoisonError<G> {}
+
+#[cfg(all(test, feature = "poison"))]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use crate::*;
+
+    #[test]
+    fn poison_on_panic() {
+        let mut group = LockGroup::new();
+        let mutex_a = Arc::new(group.mutex(1));
+        let mutex_b = Arc::new(group.mutex(2));
+        let group = Arc::new(group);
+
+        {
+            let group = group.clone();
+            let mutex_a = mutex_a.clone();
+            let mutex_b = mutex_b.clone();
+
+            let result = thread::spawn(move || {
+                let mut token = group.token();
+                let (_a, mut b) = lock2(&mut token, mutex_a.read(), mutex_b.write()).unwrap();
+
+                *b = 3;
+                panic!("Failed while holding the locks");
+            })
+            .join();
+
+            assert!(result.is_err());
+        }
+
+        assert!(mutex_a.is_poisoned());
+        assert!(mutex_b.is_poisoned());
+
+        let mut token = group.token();
+
+        {
+            let err = lock2(&mut token, mutex_a.read(), mutex_b.read()).unwrap_err();
+            let (a, b) = err.into_inner();
+
+            assert_eq!(*a, 1);
+            assert_eq!(*b, 3);
+        }
+
+        mutex_a.clear_poison();
+        assert!((mutex_a.read(),).lock(&mut token).is_ok());
+        assert!(lock2(&mut token, mutex_a.read(), mutex_b.read()).is_err());
+
+        mutex_b.clear_poison();
+        assert!(lock2(&mut token, mutex_a.read(), mutex_b.read()).is_ok());
+    }
+
+    #[test]
+    fn no_poison_without_panic() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex = group.mutex(1);
+
+        drop((mutex.write(),).lock(&mut token).unwrap());
+
+        assert!(!mutex.is_poisoned());
+    }
+}
@@ -0,0 +1,327 @@
+//! Raw lock backends
+//!
+//! A nitric `Mutex` can use any `lock_api::RawMutex` for the actual locking.
+//! Next to the default, `parking_lot::RawMutex`, this module provides
+//!
+//! * `RawSpinlock`, for very short critical sections
+//! * `RawStdMutex`, which is only based on `std::sync`
+//! * `RawNoOpMutex`, for single-threaded use (e.g. in tests)
+//!
+//! Mutexes with different backends can be freely joined. The same holds for
+//! `RwLock`s, which can use any `lock_api::RawRwLockUpgrade`.
+
+use std::{
+    cell::Cell,
+    hint,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex, PoisonError,
+    },
+    thread,
+};
+
+use lock_api::{GuardNoSend, GuardSend, RawMutex, RawRwLock, RawRwLockUpgrade};
+
+/// Object-safe version of `lock_api::RawMutex`, implemented for all raw
+/// mutexes. Allows `RawLockGuard` to dispatch to any backend.
+pub trait DynRawMutex {
+    /// Acquires the lock, blocking until it is available.
+    fn lock(&self);
+
+    /// Tries to acquire the lock without blocking.
+    fn try_lock(&self) -> bool;
+
+    /// Releases the lock.
+    fn unlock(&self);
+}
+
+impl<R: RawMutex> DynRawMutex for R {
+    #[inline]
+    fn lock(&self) {
+        RawMutex::lock(self)
+    }
+
+    #[inline]
+    fn try_lock(&self) -> bool {
+        RawMutex::try_lock(self)
+    }
+
+    #[inline]
+    fn unlock(&self) {
+        RawMutex::unlock(self)
+    }
+}
+
+/// Object-safe version of `lock_api::RawRwLockUpgrade`, implemented for all
+/// upgradable raw reader-writer locks. Allows `RawLockGuard` to dispatch to
+/// any backend.
+pub trait DynRawRwLock {
+    /// Acquires a shared lock, blocking until it is available.
+    fn lock_shared(&self);
+
+    /// Tries to acquire a shared lock without blocking.
+    fn try_lock_shared(&self) -> bool;
+
+    /// Releases a shared lock.
+    fn unlock_shared(&self);
+
+    /// Acquires an exclusive lock, blocking until it is available.
+    fn lock_exclusive(&self);
+
+    /// Tries to acquire an exclusive lock without blocking.
+    fn try_lock_exclusive(&self) -> bool;
+
+    /// Releases an exclusive lock.
+    fn unlock_exclusive(&self);
+
+    /// Acquires an upgradable lock, blocking until it is available.
+    fn lock_upgradable(&self);
+
+    /// Tries to acquire an upgradable lock without blocking.
+    fn try_lock_upgradable(&self) -> bool;
+
+    /// Releases an upgradable lock.
+    fn unlock_upgradable(&self);
+}
+
+impl<R: RawRwLockUpgrade> DynRawRwLock for R {
+    #[inline]
+    fn lock_shared(&self) {
+        RawRwLock::lock_shared(self)
+    }
+
+    #[inline]
+    fn try_lock_shared(&self) -> bool {
+        RawRwLock::try_lock_shared(self)
+    }
+
+    #[inline]
+    fn unlock_shared(&self) {
+        RawRwLock::unlock_shared(self)
+    }
+
+    #[inline]
+    fn lock_exclusive(&self) {
+        RawRwLock::lock_exclusive(self)
+    }
+
+    #[inline]
+    fn try_lock_exclusive(&self) -> bool {
+        RawRwLock::try_lock_exclusive(self)
+    }
+
+    #[inline]
+    fn unlock_exclusive(&self) {
+        RawRwLock::unlock_exclusive(self)
+    }
+
+    #[inline]
+    fn lock_upgradable(&self) {
+        RawRwLockUpgrade::lock_upgradable(self)
+    }
+
+    #[inline]
+    fn try_lock_upgradable(&self) -> bool {
+        RawRwLockUpgrade::try_lock_upgradable(self)
+    }
+
+    #[inline]
+    fn unlock_upgradable(&self) {
+        RawRwLockUpgrade::unlock_upgradable(self)
+    }
+}
+
+/// The number of times `RawSpinlock` spins before yielding to other threads.
+const SPINS_BEFORE_YIELD: u32 = 100;
+
+/// A raw spin lock, busy-waiting until the lock is available.
+///
+/// After spinning for a while, the waiting thread yields its time slice, so
+/// a holder which has been preempted (e.g. on a single core) can finish.
+///
+/// Only use this for very short critical sections.
+pub struct RawSpinlock {
+    locked: AtomicBool,
+}
+
+unsafe impl RawMutex for RawSpinlock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawSpinlock {
+        locked: AtomicBool::new(false),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        let mut spins = 0;
+
+        while !RawMutex::try_lock(self) {
+            while self.locked.load(Ordering::Relaxed) {
+                match spins < SPINS_BEFORE_YIELD {
+                    true => {
+                        spins += 1;
+                        hint::spin_loop();
+                    }
+                    false => thread::yield_now(),
+                }
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A raw mutex that is only based on `std::sync::{Mutex, Condvar}`.
+pub struct RawStdMutex {
+    locked: Mutex<bool>,
+    unlocked: Condvar,
+}
+
+unsafe impl RawMutex for RawStdMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawStdMutex {
+        locked: Mutex::new(false),
+        unlocked: Condvar::new(),
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        let locked = self.locked.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut locked = self
+            .unlocked
+            .wait_while(locked, |locked| *locked)
+            .unwrap_or_else(PoisonError::into_inner);
+
+        *locked = true;
+    }
+
+    fn try_lock(&self) -> bool {
+        let mut locked = self.locked.lock().unwrap_or_else(PoisonError::into_inner);
+
+        !std::mem::replace(&mut *locked, true)
+    }
+
+    fn unlock(&self) {
+        *self.locked.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        self.unlocked.notify_one();
+    }
+}
+
+/// A raw mutex for single-threaded use, which does not synchronize at all.
+///
+/// Since it is not `Sync`, neither are mutexes using it. It still keeps track
+/// of whether it's locked, so `lock` panics instead of blocking forever if the
+/// lock is held already.
+pub struct RawNoOpMutex {
+    locked: Cell<bool>,
+}
+
+unsafe impl RawMutex for RawNoOpMutex {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = RawNoOpMutex {
+        locked: Cell::new(false),
+    };
+
+    type GuardMarker = GuardNoSend;
+
+    fn lock(&self) {
+        assert!(
+            RawMutex::try_lock(self),
+            "`RawNoOpMutex` is locked already, this would block forever"
+        );
+    }
+
+    fn try_lock(&self) -> bool {
+        !self.locked.replace(true)
+    }
+
+    fn unlock(&self) {
+        self.locked.set(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn join_backends() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let a = group.mutex(1);
+        let b = group.mutex_with::<RawSpinlock, _>(2);
+        let c = group.mutex_with::<RawStdMutex, _>(3);
+        let d = group.mutex_with::<RawNoOpMutex, _>(4);
+
+        {
+            let (mut d, c, b, a) = (d.write(), c.read(), b.read(), a.read())
+                .lock(&mut token)
+                .unwrap();
+
+            *d += *a + *b + *c;
+        }
+
+        let (d,) = (d.read(),).lock(&mut token).unwrap();
+        assert_eq!(*d, 10);
+    }
+
+    #[test]
+    fn threads() {
+        let mut group = LockGroup::new();
+        let mutexes = Arc::new((
+            group.mutex_with::<RawStdMutex, _>(0),
+            group.mutex_with::<RawSpinlock, _>(0),
+        ));
+        let group = Arc::new(group);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let group = group.clone();
+                let mutexes = mutexes.clone();
+
+                thread::spawn(move || {
+                    let mut token = group.token();
+
+                    for _ in 0..1000 {
+                        let (mut b, mut a) =
+                            lock2(&mut token, mutexes.1.write(), mutexes.0.write()).unwrap();
+
+                        *a += 1;
+                        *b += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut token = group.token();
+        let (a, b) = lock2(&mut token, mutexes.0.read(), mutexes.1.read()).unwrap();
+
+        assert_eq!((*a, *b), (4000, 4000));
+    }
+
+    #[test]
+    #[should_panic(expected = "block forever")]
+    fn no_op_locked() {
+        let raw = RawNoOpMutex::INIT;
+
+        RawMutex::lock(&raw);
+        RawMutex::lock(&raw);
+    }
+}
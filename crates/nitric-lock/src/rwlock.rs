@@ -0,0 +1,439 @@
+use std::{
+    cell::UnsafeCell,
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+use lock_api::{RawRwLock, RawRwLockUpgrade};
+
+use crate::{
+    group::{guard_created, guard_dropped, highest_held},
+    poison::{Poison, PoisonGuard},
+    stats::{SharedStats, StatsGuard},
+    LockId, LockInfo, RawLockGuard, ReadLock, UpgradableLock, Waiters, WriteLock,
+};
+
+pub fn new_rw_lock<T, R: RawRwLockUpgrade>(
+    data: T,
+    id: LockId,
+    stats: SharedStats,
+) -> RwLock<T, R> {
+    RwLock {
+        data: UnsafeCell::new(data),
+        id,
+        raw: R::INIT,
+        waiters: Waiters::new(),
+        poison: Poison::new(),
+        stats,
+        #[cfg(feature = "deadlock-detection")]
+        _registration: crate::deadlock::Registration(id),
+    }
+}
+
+/// A reader-writer lock, allocated from a `LockGroup`.
+///
+/// Besides `read` and `write`, it can be acquired with `upgradable`, which
+/// allows concurrent readers but can later be upgraded to a write guard with
+/// `RwLockUpgradableReadGuard::upgrade`.
+///
+/// Only write guards poison the lock.
+pub struct RwLock<T, R = parking_lot::RawRwLock> {
+    data: UnsafeCell<T>,
+    id: LockId,
+    raw: R,
+    waiters: Waiters,
+    poison: Poison,
+    stats: SharedStats,
+    /// Removes the lock from the deadlock detector when dropped.
+    #[cfg(feature = "deadlock-detection")]
+    _registration: crate::deadlock::Registration,
+}
+
+unsafe impl<T: Send + Sync, R: RawRwLock + Sync> Sync for RwLock<T, R> {}
+
+impl<T, R: RawRwLockUpgrade> RwLock<T, R> {
+    pub fn lock_id(&self) -> LockId {
+        self.id
+    }
+
+    /// Checks if this lock is poisoned, which happens if a thread panicked
+    /// while holding a write guard. Always `false` without the `poison`
+    /// feature.
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    /// Clears the poisoned state of this lock, marking the data as recovered.
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+
+    /// Returns a mutable reference to the data, which requires no locking
+    /// since the lock is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the lock, returning the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Returns the raw lock.
+    ///
+    /// # Safety
+    ///
+    /// The raw lock must only be locked in the order of the lock IDs, and only
+    /// be unlocked if it has been locked before.
+    pub unsafe fn raw(&self) -> &R {
+        &self.raw
+    }
+
+    fn info<'a>(&'a self, guard: RawLockGuard<'a>) -> LockInfo<'a> {
+        LockInfo {
+            id: self.id,
+            guard,
+            waiters: &self.waiters,
+            poison: &self.poison,
+            stats: &self.stats,
+        }
+    }
+
+    fn release(&self, stats: &StatsGuard) {
+        self.stats.done(stats);
+
+        #[cfg(feature = "deadlock-detection")]
+        crate::deadlock::lock_released(self.id);
+    }
+
+    fn released(&self) {
+        self.waiters.wake_all();
+
+        guard_dropped(self.id);
+    }
+}
+
+impl<'a, T, R> ReadLock<'a> for &'a RwLock<T, R>
+where
+    T: 'a,
+    R: RawRwLockUpgrade + 'a,
+{
+    type Output = RwLockReadGuard<'a, T, R>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        self.info(RawLockGuard::RawRwLockShared(&self.raw))
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as ReadLock<'a>>::Output {
+        guard_created(self.id);
+
+        RwLockReadGuard {
+            marker: PhantomData,
+            lock: self,
+            stats: self.stats.guard(),
+        }
+    }
+}
+
+impl<'a, T, R> WriteLock<'a> for &'a RwLock<T, R>
+where
+    T: 'a,
+    R: RawRwLockUpgrade + 'a,
+{
+    type Output = RwLockWriteGuard<'a, T, R>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        self.info(RawLockGuard::RawRwLockExclusive(&self.raw))
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as WriteLock<'a>>::Output {
+        guard_created(self.id);
+
+        RwLockWriteGuard {
+            marker: PhantomData,
+            lock: self,
+            poison: self.poison.guard(),
+            stats: self.stats.guard(),
+        }
+    }
+}
+
+impl<'a, T, R> UpgradableLock<'a> for &'a RwLock<T, R>
+where
+    T: 'a,
+    R: RawRwLockUpgrade + 'a,
+{
+    type Output = RwLockUpgradableReadGuard<'a, T, R>;
+
+    unsafe fn lock_info(&self) -> LockInfo<'a> {
+        self.info(RawLockGuard::RawRwLockUpgradable(&self.raw))
+    }
+
+    unsafe fn lock_unchecked(self) -> <Self as UpgradableLock<'a>>::Output {
+        guard_created(self.id);
+
+        RwLockUpgradableReadGuard {
+            marker: PhantomData,
+            lock: self,
+            stats: self.stats.guard(),
+        }
+    }
+}
+
+/// Shared read access to the data of a `RwLock`.
+pub struct RwLockReadGuard<'a, T, R: RawRwLockUpgrade = parking_lot::RawRwLock> {
+    marker: PhantomData<(&'a T, *mut ())>,
+    lock: &'a RwLock<T, R>,
+    stats: StatsGuard,
+}
+
+unsafe impl<T: Sync, R: RawRwLockUpgrade + Sync> Sync for RwLockReadGuard<'_, T, R> {}
+
+impl<T: Debug, R: RawRwLockUpgrade> Debug for RwLockReadGuard<'_, T, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> Deref for RwLockReadGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &<Self as Deref>::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> Drop for RwLockReadGuard<'_, T, R> {
+    fn drop(&mut self) {
+        self.lock.release(&self.stats);
+        self.lock.raw.unlock_shared();
+        self.lock.released();
+    }
+}
+
+/// Exclusive write access to the data of a `RwLock`.
+pub struct RwLockWriteGuard<'a, T, R: RawRwLockUpgrade = parking_lot::RawRwLock> {
+    marker: PhantomData<(&'a mut T, *mut ())>,
+    lock: &'a RwLock<T, R>,
+    poison: PoisonGuard,
+    stats: StatsGuard,
+}
+
+unsafe impl<T: Sync, R: RawRwLockUpgrade + Sync> Sync for RwLockWriteGuard<'_, T, R> {}
+
+impl<T: Debug, R: RawRwLockUpgrade> Debug for RwLockWriteGuard<'_, T, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> Deref for RwLockWriteGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &<Self as Deref>::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> DerefMut for RwLockWriteGuard<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut <Self as Deref>::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> Drop for RwLockWriteGuard<'_, T, R> {
+    fn drop(&mut self) {
+        self.lock.poison.done(&self.poison);
+        self.lock.release(&self.stats);
+        self.lock.raw.unlock_exclusive();
+        self.lock.released();
+    }
+}
+
+/// Upgradable read access to the data of a `RwLock`.
+///
+/// Other threads can still read the data, but no other thread can acquire the
+/// lock for writing or in upgradable mode.
+pub struct RwLockUpgradableReadGuard<'a, T, R: RawRwLockUpgrade = parking_lot::RawRwLock> {
+    marker: PhantomData<(&'a T, *mut ())>,
+    lock: &'a RwLock<T, R>,
+    stats: StatsGuard,
+}
+
+unsafe impl<T: Sync, R: RawRwLockUpgrade + Sync> Sync for RwLockUpgradableReadGuard<'_, T, R> {}
+
+impl<'a, T, R: RawRwLockUpgrade> RwLockUpgradableReadGuard<'a, T, R> {
+    /// Upgrades the guard to a write guard, blocking until all readers have
+    /// released the lock.
+    ///
+    /// # Panics
+    ///
+    /// Waiting for the readers is only deadlock-free if none of them waits for
+    /// a lock held by this thread. Thus, this panics unless the lock is the
+    /// highest-ordered lock held by the current thread.
+    pub fn upgrade(guard: Self) -> RwLockWriteGuard<'a, T, R> {
+        let (lock, stats) = (guard.lock, guard.stats);
+
+        assert_eq!(
+            highest_held(),
+            Some(lock.id),
+            "Can only upgrade the highest-ordered lock held by the thread"
+        );
+
+        #[cfg(feature = "deadlock-detection")]
+        crate::deadlock::upgrade_requested(lock.id);
+
+        // The lock stays held, so neither release it nor update the held
+        // guards of this thread.
+        mem::forget(guard);
+        lock.raw.upgrade();
+
+        RwLockWriteGuard {
+            marker: PhantomData,
+            lock,
+            poison: lock.poison.guard(),
+            stats,
+        }
+    }
+}
+
+impl<T: Debug, R: RawRwLockUpgrade> Debug for RwLockUpgradableReadGuard<'_, T, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> Deref for RwLockUpgradableReadGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &<Self as Deref>::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T, R: RawRwLockUpgrade> Drop for RwLockUpgradableReadGuard<'_, T, R> {
+    fn drop(&mut self) {
+        self.lock.release(&self.stats);
+        self.lock.raw.unlock_upgradable();
+        self.lock.released();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn readers() {
+        let mut group = LockGroup::new();
+        let lock = Arc::new(group.rw_lock(5));
+        let group = Arc::new(group);
+
+        let mut token = group.token();
+        let (a,) = (lock.read(),).lock(&mut token).unwrap();
+
+        let other = {
+            let group = group.clone();
+            let lock = lock.clone();
+
+            thread::spawn(move || {
+                let mut token = group.token();
+                let (b,) = (lock.read(),).lock(&mut token).unwrap();
+
+                *b
+            })
+        };
+
+        assert_eq!(other.join().unwrap(), *a);
+    }
+
+    #[test]
+    fn upgrade() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let mutex = group.mutex(1);
+        let lock = group.rw_lock(2);
+
+        {
+            let (guard, a) = lock2(&mut token, lock.upgradable(), mutex.read()).unwrap();
+
+            let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+            *guard += *a;
+        }
+
+        let (b,) = (lock.read(),).lock(&mut token).unwrap();
+        assert_eq!(*b, 3);
+    }
+
+    #[test]
+    fn upgrade_waits_for_readers() {
+        let mut group = LockGroup::new();
+        let lock = Arc::new(group.rw_lock(0));
+        let group = Arc::new(group);
+        let upgraded = Arc::new(AtomicBool::new(false));
+
+        let mut token = group.token();
+        let (reader,) = (lock.read(),).lock(&mut token).unwrap();
+
+        let writer = {
+            let group = group.clone();
+            let lock = lock.clone();
+            let upgraded = upgraded.clone();
+
+            thread::spawn(move || {
+                let mut token = group.token();
+                let (guard,) = (lock.upgradable(),).lock(&mut token).unwrap();
+
+                let mut guard = RwLockUpgradableReadGuard::upgrade(guard);
+                upgraded.store(true, Ordering::SeqCst);
+                *guard += 1;
+            })
+        };
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(!upgraded.load(Ordering::SeqCst));
+        assert_eq!(*reader, 0);
+        drop(reader);
+
+        writer.join().unwrap();
+        assert_eq!(*(lock.read(),).lock(&mut token).unwrap().0, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "highest-ordered")]
+    fn upgrade_not_highest() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let lock = group.rw_lock(1);
+        let mutex = group.mutex(2);
+
+        let (guard, _m) = lock2(&mut token, lock.upgradable(), mutex.write()).unwrap();
+
+        let _ = RwLockUpgradableReadGuard::upgrade(guard);
+    }
+
+    #[test]
+    #[should_panic(expected = "twice")]
+    fn read_and_upgradable() {
+        let mut group = LockGroup::new();
+        let mut token = group.token();
+
+        let lock = group.rw_lock(1);
+
+        let _ = lock2(&mut token, lock.read(), lock.upgradable()).unwrap();
+    }
+}
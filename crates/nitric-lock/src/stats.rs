@@ -0,0 +1,255 @@
+//! Lock statistics
+//!
+//! With the `stats` feature enabled, every lock records how often it was
+//! acquired, how often it was contended, how long threads waited for it and
+//! for how long it was held at most. A snapshot of all locks of a group can be
+//! retrieved with `LockGroup::stats`. Without the feature, nothing is
+//! recorded.
+
+use std::time::Duration;
+
+use crate::RawLockGuard;
+
+#[cfg(feature = "stats")]
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+#[cfg(feature = "stats")]
+use crate::LockId;
+
+/// The statistics recorded for a lock.
+#[derive(Debug, Default)]
+pub struct Stats {
+    #[cfg(feature = "stats")]
+    acquisitions: AtomicU64,
+    #[cfg(feature = "stats")]
+    contentions: AtomicU64,
+    #[cfg(feature = "stats")]
+    wait_nanos: AtomicU64,
+    #[cfg(feature = "stats")]
+    max_hold_nanos: AtomicU64,
+}
+
+/// The way locks store their `Stats`; shared with the `LockGroup` if the
+/// `stats` feature is enabled.
+#[cfg(feature = "stats")]
+pub(crate) type SharedStats = Arc<Stats>;
+/// The way locks store their `Stats`; shared with the `LockGroup` if the
+/// `stats` feature is enabled.
+#[cfg(not(feature = "stats"))]
+pub(crate) type SharedStats = Stats;
+
+impl Stats {
+    /// Creates empty statistics.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Acquires `guard`, blocking if necessary, and records the acquisition.
+    #[inline]
+    pub fn acquire(&self, guard: &RawLockGuard<'_>) {
+        #[cfg(feature = "stats")]
+        {
+            if !guard.try_lock() {
+                let start = Instant::now();
+                guard.lock();
+
+                self.record_wait(start.elapsed());
+            }
+
+            self.record_acquired();
+        }
+
+        #[cfg(not(feature = "stats"))]
+        guard.lock();
+    }
+
+    /// Records that acquiring the lock had to wait for `waited` because it
+    /// was held already.
+    #[inline]
+    pub fn record_wait(&self, waited: Duration) {
+        #![allow(unused)]
+
+        #[cfg(feature = "stats")]
+        {
+            self.contentions.fetch_add(1, Ordering::Relaxed);
+            self.wait_nanos.fetch_add(nanos(waited), Ordering::Relaxed);
+        }
+    }
+
+    /// Records that the lock has been acquired.
+    #[inline]
+    pub fn record_acquired(&self) {
+        #[cfg(feature = "stats")]
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Must be called when a guard of the lock is created, to measure for how
+    /// long the guard holds the lock.
+    #[inline]
+    pub fn guard(&self) -> StatsGuard {
+        StatsGuard {
+            #[cfg(feature = "stats")]
+            locked_at: Instant::now(),
+        }
+    }
+
+    /// Must be called when a guard of the lock is dropped, before unlocking.
+    #[inline]
+    pub fn done(&self, guard: &StatsGuard) {
+        #![allow(unused)]
+
+        #[cfg(feature = "stats")]
+        self.max_hold_nanos
+            .fetch_max(nanos(guard.locked_at.elapsed()), Ordering::Relaxed);
+    }
+
+    /// Creates a snapshot of these statistics.
+    #[cfg(feature = "stats")]
+    pub fn snapshot(&self, id: LockId, name: Option<String>) -> LockStats {
+        LockStats {
+            id,
+            name,
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contentions: self.contentions.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            max_hold: Duration::from_nanos(self.max_hold_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Remembers when a guard started holding its lock. Every guard has its own,
+/// so the hold times of concurrent readers are measured independently.
+#[derive(Clone, Copy, Debug)]
+pub struct StatsGuard {
+    #[cfg(feature = "stats")]
+    locked_at: Instant,
+}
+
+/// A snapshot of the statistics of a lock, as returned by `LockGroup::stats`.
+#[cfg(feature = "stats")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LockStats {
+    /// The ID of the lock.
+    pub id: LockId,
+    /// The name given to the lock on creation, if any.
+    pub name: Option<String>,
+    /// The number of times the lock has been acquired.
+    pub acquisitions: u64,
+    /// The number of acquisitions that had to wait because the lock was held
+    /// already.
+    pub contentions: u64,
+    /// The total time spent waiting for the lock.
+    pub total_wait: Duration,
+    /// The longest time the lock was held at once.
+    pub max_hold: Duration,
+}
+
+#[cfg(feature = "stats")]
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u128::from(u64::MAX)) as u64
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+    };
+
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn snapshot() {
+        let mut group = LockGroup::new();
+        let mutex_a = Arc::new(group.named_mutex("counter", 0));
+        let mutex_b = group.mutex(0);
+        let group = Arc::new(group);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let group = group.clone();
+                let mutex_a = mutex_a.clone();
+
+                thread::spawn(move || {
+                    let mut token = group.token();
+
+                    for _ in 0..100 {
+                        *(mutex_a.write(),).lock(&mut token).unwrap().0 += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut token = group.token();
+        lock2(&mut token, mutex_a.read(), mutex_b.read()).unwrap();
+
+        let stats = group.stats();
+        let a = &stats[&mutex_a.lock_id()];
+        let b = &stats[&mutex_b.lock_id()];
+
+        assert_eq!(a.name.as_deref(), Some("counter"));
+        assert_eq!(a.acquisitions, 401);
+        assert!(a.contentions <= 400);
+        assert_eq!(b.name, None);
+        assert_eq!(b.acquisitions, 1);
+        assert_eq!(b.contentions, 0);
+        assert_eq!(b.total_wait, Duration::from_secs(0));
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let mut group = LockGroup::new();
+        let lock = Arc::new(group.rw_lock(0));
+        let group = Arc::new(group);
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel();
+        let handle = {
+            let group = group.clone();
+            let lock = lock.clone();
+
+            thread::spawn(move || {
+                let mut token = group.token();
+                let _first = (lock.read(),).lock(&mut token).unwrap();
+
+                locked_tx.send(()).unwrap();
+                release_rx.recv().unwrap();
+            })
+        };
+
+        locked_rx.recv().unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        // The second reader must not reset the hold time of the first one.
+        let mut token = group.token();
+        drop((lock.read(),).lock(&mut token).unwrap());
+        release_tx.send(()).unwrap();
+        handle.join().unwrap();
+
+        assert!(group.stats()[&lock.lock_id()].max_hold >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn dropped_locks() {
+        let mut group = LockGroup::new();
+
+        let mutex = group.mutex(());
+        drop(group.mutex(()));
+
+        let stats = group.stats();
+
+        assert_eq!(stats.len(), 1);
+        assert!(stats.contains_key(&mutex.lock_id()));
+    }
+}
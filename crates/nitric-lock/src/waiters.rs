@@ -0,0 +1,57 @@
+use std::{
+    sync::atomic::{fence, AtomicBool, Ordering},
+    task::Waker,
+};
+
+use parking_lot::Mutex;
+
+/// A queue of tasks waiting for a lock to be released.
+///
+/// Every lock that supports asynchronous acquisition owns one of these and
+/// calls `wake_all` after it was unlocked.
+#[derive(Default)]
+pub struct Waiters {
+    queue: Mutex<Vec<Waker>>,
+    non_empty: AtomicBool,
+}
+
+impl Waiters {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers `waker` to be woken up on the next call to `wake_all`.
+    pub fn register(&self, waker: &Waker) {
+        let mut queue = self.queue.lock();
+
+        if !queue.iter().any(|w| w.will_wake(waker)) {
+            queue.push(waker.clone());
+        }
+
+        self.non_empty.store(true, Ordering::SeqCst);
+        // pairs with the fence in `wake_all`, so either the task retrying the
+        // lock after registering sees the unlock, or `wake_all` sees the waker
+        fence(Ordering::SeqCst);
+    }
+
+    /// Wakes up all registered tasks.
+    pub fn wake_all(&self) {
+        fence(Ordering::SeqCst);
+
+        if !self.non_empty.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let wakers = {
+            let mut queue = self.queue.lock();
+            self.non_empty.store(false, Ordering::SeqCst);
+
+            std::mem::take(&mut *queue)
+        };
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
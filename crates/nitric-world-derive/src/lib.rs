@@ -0,0 +1,106 @@
+//! Implements `#[derive(Fetch)]` for `nitric-world`.
+//!
+//! Use it through the `derive` feature of `nitric-world` rather than
+//! depending on this crate directly.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Lifetime,
+    Result,
+};
+
+/// Derives `Fetch` for a struct whose fields all implement `Fetch`.
+///
+/// The struct may have at most one lifetime parameter, which is used as the
+/// lifetime of the world borrow. Its `Keys` is a tuple with the keys of all
+/// fields, in declaration order.
+#[proc_macro_derive(Fetch)]
+pub fn derive_fetch(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    impl_fetch(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn impl_fetch(input: &DeriveInput) -> Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "`Fetch` can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut lifetimes = input.generics.lifetimes();
+    let lifetime = match (lifetimes.next(), lifetimes.next()) {
+        (Some(def), None) => def.lifetime.clone(),
+        (None, _) => Lifetime::new("'__a", Span::call_site()),
+        (Some(_), Some(extra)) => {
+            return Err(Error::new_spanned(
+                extra,
+                "`Fetch` can only be derived for structs with at most one lifetime",
+            ))
+        }
+    };
+
+    let tys: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let indices = (0..tys.len()).map(syn::Index::from);
+    let body = match fields {
+        Fields::Named(_) => {
+            let idents = fields.iter().map(|field| &field.ident);
+
+            quote!(#name { #(#idents: <#tys as ::nitric_world::Fetch<#lifetime, __K>>::fetch(world, keys.#indices)?,)* })
+        }
+        Fields::Unnamed(_) => {
+            quote!(#name(#(<#tys as ::nitric_world::Fetch<#lifetime, __K>>::fetch(world, keys.#indices)?,)*))
+        }
+        Fields::Unit => quote!(#name),
+    };
+
+    let mut generics = input.generics.clone();
+    if input.generics.lifetimes().next().is_none() {
+        generics
+            .params
+            .insert(0, GenericParam::Lifetime(parse_quote!(#lifetime)));
+    }
+    generics
+        .params
+        .push(parse_quote!(__K: ::std::hash::Hash + ::std::cmp::Eq));
+    {
+        let where_clause = generics.make_where_clause();
+        for ty in &tys {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::nitric_world::Fetch<#lifetime, __K>));
+        }
+    }
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::nitric_world::Fetch<#lifetime, __K> for #name #ty_generics
+        #where_clause
+        {
+            type Keys = (#(<#tys as ::nitric_world::Fetch<#lifetime, __K>>::Keys,)*);
+
+            fn fetch(
+                world: &#lifetime ::nitric_world::World<__K>,
+                keys: Self::Keys,
+            ) -> ::std::result::Result<Self, ::nitric_world::FetchError> {
+                let _ = (&world, &keys);
+
+                ::std::result::Result::Ok(#body)
+            }
+        }
+    })
+}
@@ -0,0 +1,263 @@
+//! Resource cells with runtime borrow tracking.
+
+use std::{
+    cell::UnsafeCell,
+    fmt::{self, Debug, Formatter},
+    mem::{self, ManuallyDrop},
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{claim::entered, FetchError, Resource};
+
+const WRITING: usize = usize::MAX;
+
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(0);
+
+/// Stores a resource together with its borrow state, allowing shared and
+/// mutable borrows through `&self` which are checked at runtime.
+pub(crate) struct ResourceCell {
+    /// Identifies the cell for the whole process, so a `Claim` can tell it
+    /// apart from a cell which replaced it.
+    serial: u64,
+    /// The number of shared borrows, or `WRITING` if borrowed mutably.
+    flag: AtomicUsize,
+    /// The ID of the `Claim` holding the cell exclusively, or 0. Only code
+    /// which entered that claim can borrow the resource.
+    owner: AtomicU64,
+    /// The change tick of the last mutable access, if changes are tracked.
+    changed: AtomicU64,
+    value: UnsafeCell<Box<dyn Resource>>,
+}
+
+// The value is only accessed according to the borrow flag, and `Resource`
+// requires `Send + Sync`.
+unsafe impl Sync for ResourceCell {}
+
+impl ResourceCell {
+    pub fn new(value: Box<dyn Resource>, tick: u64) -> Self {
+        ResourceCell {
+            serial: NEXT_SERIAL.fetch_add(1, Ordering::Relaxed),
+            flag: AtomicUsize::new(0),
+            owner: AtomicU64::new(0),
+            changed: AtomicU64::new(tick),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> Box<dyn Resource> {
+        self.value.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut dyn Resource {
+        &mut **self.value.get_mut()
+    }
+
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    pub fn changed(&self) -> u64 {
+        self.changed.load(Ordering::Relaxed)
+    }
+
+    pub fn mark_changed(&self, tick: u64) {
+        self.changed.store(tick, Ordering::Relaxed);
+    }
+
+    pub fn borrow(&self, type_name: &'static str) -> Result<Ref<'_, dyn Resource>, FetchError> {
+        let mut current = self.flag.load(Ordering::Relaxed);
+
+        loop {
+            if current == WRITING {
+                return Err(FetchError::BorrowedMut { type_name });
+            }
+
+            let new = current
+                .checked_add(1)
+                .filter(|&new| new != WRITING)
+                .expect("Too many shared borrows of a resource");
+
+            match self
+                .flag
+                .compare_exchange_weak(current, new, Ordering::SeqCst, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+
+        if !self.is_accessible() {
+            self.flag.fetch_sub(1, Ordering::Release);
+
+            return Err(FetchError::BorrowedMut { type_name });
+        }
+
+        Ok(Ref {
+            flag: &self.flag,
+            value: unsafe { &**self.value.get() },
+        })
+    }
+
+    pub fn borrow_mut(
+        &self,
+        type_name: &'static str,
+    ) -> Result<RefMut<'_, dyn Resource>, FetchError> {
+        match self
+            .flag
+            .compare_exchange(0, WRITING, Ordering::SeqCst, Ordering::Relaxed)
+        {
+            Ok(_) => {}
+            Err(WRITING) => return Err(FetchError::BorrowedMut { type_name }),
+            Err(_) => return Err(FetchError::Borrowed { type_name }),
+        }
+
+        if !self.is_accessible() {
+            self.flag.store(0, Ordering::Release);
+
+            return Err(FetchError::BorrowedMut { type_name });
+        }
+
+        Ok(RefMut {
+            flag: &self.flag,
+            value: unsafe { &mut **self.value.get() },
+        })
+    }
+
+    /// Keeps the resource borrowed immutably until `release_shared`.
+    pub fn claim_shared(&self, type_name: &'static str) -> Result<(), FetchError> {
+        self.borrow(type_name).map(mem::forget)
+    }
+
+    pub fn release_shared(&self) {
+        self.flag.fetch_sub(1, Ordering::Release);
+    }
+
+    /// Reserves the resource for the claim `owner` until `release_exclusive`.
+    /// Unlike a mutable borrow, this still allows borrowing the resource
+    /// from code which entered the claim.
+    pub fn claim_exclusive(&self, owner: u64, type_name: &'static str) -> Result<(), FetchError> {
+        match self
+            .owner
+            .compare_exchange(0, owner, Ordering::SeqCst, Ordering::Relaxed)
+        {
+            Ok(_) => {}
+            Err(current) if current == owner => return Ok(()),
+            Err(_) => return Err(FetchError::BorrowedMut { type_name }),
+        }
+
+        // Borrows check the owner after updating the flag, so either they or
+        // the claim notice each other.
+        let error = match self.flag.load(Ordering::SeqCst) {
+            0 => return Ok(()),
+            WRITING => FetchError::BorrowedMut { type_name },
+            _ => FetchError::Borrowed { type_name },
+        };
+        self.owner.store(0, Ordering::Release);
+
+        Err(error)
+    }
+
+    pub fn release_exclusive(&self) {
+        self.owner.store(0, Ordering::Release);
+    }
+
+    /// Checks if the current thread may borrow the resource, which is the
+    /// case unless it is claimed exclusively by a claim the thread did not
+    /// enter.
+    fn is_accessible(&self) -> bool {
+        match self.owner.load(Ordering::SeqCst) {
+            0 => true,
+            owner => entered(owner),
+        }
+    }
+}
+
+/// A shared borrow of a resource, returned by `World::fetch` and `World::get`.
+pub struct Ref<'a, T: ?Sized> {
+    value: &'a T,
+    flag: &'a AtomicUsize,
+}
+
+impl<'a> Ref<'a, dyn Resource> {
+    pub(crate) fn downcast<T: Resource>(self) -> Ref<'a, T> {
+        let this = ManuallyDrop::new(self);
+
+        Ref {
+            value: this
+                .value
+                .downcast_ref()
+                .expect("Resource stored under wrong type"),
+            flag: this.flag,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: Debug + ?Sized> Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.value, f)
+    }
+}
+
+impl<T: ?Sized> Drop for Ref<'_, T> {
+    fn drop(&mut self) {
+        self.flag.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// A mutable borrow of a resource, returned by `World::fetch_mut`.
+pub struct RefMut<'a, T: ?Sized> {
+    value: &'a mut T,
+    flag: &'a AtomicUsize,
+}
+
+impl<'a> RefMut<'a, dyn Resource> {
+    pub(crate) fn downcast<T: Resource>(self) -> RefMut<'a, T> {
+        let this = ManuallyDrop::new(self);
+        // `this` is never used or dropped again, so the reference can be moved
+        // out of it.
+        let value: &'a mut dyn Resource = unsafe { ptr::read(&this.value) };
+
+        RefMut {
+            value: value
+                .downcast_mut()
+                .expect("Resource stored under wrong type"),
+            flag: this.flag,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RefMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: ?Sized> DerefMut for RefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<T: Debug + ?Sized> Debug for RefMut<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.value, f)
+    }
+}
+
+impl<T: ?Sized> Drop for RefMut<'_, T> {
+    fn drop(&mut self) {
+        self.flag.store(0, Ordering::Release);
+    }
+}
@@ -0,0 +1,120 @@
+//! Opt-in change tracking for resources.
+
+use std::{
+    any::TypeId,
+    borrow::Borrow,
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use hashbrown::HashMap;
+
+/// Assigns change ticks and remembers removed resources.
+pub(crate) struct Changes<K> {
+    enabled: bool,
+    /// The last tick handed out.
+    tick: AtomicU64,
+    /// The names and removal ticks of removed resources, by type and key.
+    removed: HashMap<TypeId, (&'static str, HashMap<K, u64>)>,
+}
+
+impl<K: Hash + Eq> Default for Changes<K> {
+    fn default() -> Self {
+        Changes {
+            enabled: false,
+            tick: AtomicU64::new(0),
+            removed: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Changes<K> {
+    pub fn enable(&mut self) -> u64 {
+        self.enabled = true;
+
+        self.next_tick()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick.load(Ordering::Relaxed)
+    }
+
+    /// Returns a new tick for a change, or `0` if changes are not tracked.
+    pub fn next_tick(&self) -> u64 {
+        match self.enabled {
+            true => self.tick.fetch_add(1, Ordering::Relaxed) + 1,
+            false => 0,
+        }
+    }
+
+    pub fn removed(&mut self, id: TypeId, type_name: &'static str, k: K) {
+        if self.enabled {
+            let tick = self.next_tick();
+
+            self.removed
+                .entry(id)
+                .or_insert_with(|| (type_name, HashMap::new()))
+                .1
+                .insert(k, tick);
+        }
+    }
+
+    pub fn inserted(&mut self, id: TypeId, k: &K) {
+        if let Some((_, removed)) = self.removed.get_mut(&id) {
+            removed.remove(k);
+        }
+    }
+
+    pub fn removed_tick<Q>(&self, id: TypeId, k: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.removed.get(&id)?.1.get(k).cloned()
+    }
+
+    pub fn removed_since(&self, tick: u64) -> impl Iterator<Item = Change<'_, K>> {
+        self.removed
+            .iter()
+            .flat_map(move |(&type_id, (type_name, removed))| {
+                removed
+                    .iter()
+                    .filter(move |&(_, &removed)| removed > tick)
+                    .map(move |(key, &tick)| Change {
+                        type_id,
+                        type_name,
+                        key,
+                        tick,
+                        removed: true,
+                    })
+            })
+    }
+
+    pub fn clear_removed(&mut self, tick: u64) {
+        for (_, removed) in self.removed.values_mut() {
+            removed.retain(|_, &mut removed| removed > tick);
+        }
+
+        self.removed.retain(|_, (_, removed)| !removed.is_empty());
+    }
+}
+
+/// A resource which changed since a given tick, yielded by
+/// `World::changes_since`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Change<'a, K> {
+    /// The type of the resource.
+    pub type_id: TypeId,
+    /// The name of the type, as given by `std::any::type_name`.
+    pub type_name: &'static str,
+    /// The key of the resource.
+    pub key: &'a K,
+    /// The tick of the latest change.
+    pub tick: u64,
+    /// `true` if the resource was removed.
+    pub removed: bool,
+}
@@ -0,0 +1,167 @@
+//! Claiming resources for longer than a borrow.
+
+use std::{
+    any::TypeId,
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+static NEXT_CLAIM: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// The IDs of the claims entered by the current thread.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Checks if the current thread entered the claim `id`.
+pub(crate) fn entered(id: u64) -> bool {
+    ENTERED.with(|entered| entered.borrow().contains(&id))
+}
+
+/// Hashes the key of a resource, as expected by `World::claim`.
+///
+/// Keys which borrow as the same value have the same hash, e.g. a `String`
+/// and the `&str` it derefs to.
+pub fn key_hash<Q: ?Sized + Hash>(key: &Q) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Resources of a `World` reserved by `World::claim`, without borrowing the
+/// world.
+///
+/// A claim keeps its resources reserved until it is passed to
+/// `World::release`; dropping it instead leaks the reservation.
+#[derive(Debug)]
+#[must_use = "a claim has to be released with `World::release`"]
+pub struct Claim {
+    id: u64,
+    /// The type and serial of every claimed cell, and whether the cell is
+    /// claimed exclusively.
+    cells: Vec<(TypeId, u64, bool)>,
+}
+
+impl Claim {
+    pub(crate) fn new() -> Self {
+        Claim {
+            id: NEXT_CLAIM.fetch_add(1, Ordering::Relaxed),
+            cells: Vec::new(),
+        }
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn push(&mut self, type_id: TypeId, serial: u64, exclusive: bool) {
+        self.cells.push((type_id, serial, exclusive));
+    }
+
+    pub(crate) fn into_cells(self) -> Vec<(TypeId, u64, bool)> {
+        self.cells
+    }
+
+    /// Runs `f`, allowing it to borrow the resources claimed exclusively
+    /// from the current thread.
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        /// Leaves the claim even if `f` panics.
+        struct Leave;
+
+        impl Drop for Leave {
+            fn drop(&mut self) {
+                ENTERED.with(|entered| entered.borrow_mut().pop());
+            }
+        }
+
+        ENTERED.with(|entered| entered.borrow_mut().push(self.id));
+        let _leave = Leave;
+
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::TypeId;
+
+    use super::*;
+    use crate::{FetchError, World};
+
+    fn id(key: &str) -> (TypeId, u64) {
+        (TypeId::of::<u32>(), key_hash(key))
+    }
+
+    #[test]
+    fn exclusive() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.insert("b", 2u32);
+
+        let claim = world.claim(&[], &[id("a")]).unwrap();
+        assert!(world.try_fetch::<u32, _>("a").is_err());
+        assert!(world.try_fetch_mut::<u32, _>("a").is_err());
+        assert!(world.try_fetch_mut::<u32, _>("b").is_ok());
+
+        claim.enter(|| *world.fetch_mut::<u32, _>("a") += 1);
+        assert!(world.try_fetch::<u32, _>("a").is_err());
+
+        world.release(claim);
+        assert_eq!(*world.fetch::<u32, _>("a"), 2);
+    }
+
+    #[test]
+    fn shared() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+
+        let claim = world.claim(&[id("a")], &[]).unwrap();
+        assert_eq!(*world.fetch::<u32, _>("a"), 1);
+        assert_eq!(
+            world.try_fetch_mut::<u32, _>("a").err(),
+            Some(FetchError::Borrowed { type_name: "u32" })
+        );
+
+        world.release(claim);
+        assert!(world.try_fetch_mut::<u32, _>("a").is_ok());
+    }
+
+    #[test]
+    fn conflicting() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.insert("b", 2u32);
+
+        {
+            let _b = world.fetch::<u32, _>("b");
+            assert!(world.claim(&[], &[id("a"), id("b")]).is_err());
+        }
+        // Nothing stayed claimed
+        assert!(world.try_fetch_mut::<u32, _>("a").is_ok());
+
+        let claim = world.claim(&[id("a")], &[id("a"), id("b")]).unwrap();
+        assert!(world.claim(&[id("b")], &[]).is_err());
+
+        world.release(claim);
+        world.release(world.claim(&[id("b")], &[]).unwrap());
+    }
+
+    #[test]
+    fn replaced() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+
+        let claim = world.claim(&[], &[id("a")]).unwrap();
+        // Mutable access to the world is not affected by claims
+        world.insert("a", 2u32);
+        assert_eq!(*world.fetch::<u32, _>("a"), 2);
+
+        // Releasing the claim leaves the new resource alone
+        let _a = world.fetch_mut::<u32, _>("a");
+        world.release(claim);
+        assert!(world.try_fetch::<u32, _>("a").is_err());
+    }
+}
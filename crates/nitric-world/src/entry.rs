@@ -0,0 +1,166 @@
+//! An entry API for resources, mirroring the one of `HashMap`.
+
+use std::{
+    any::{type_name, TypeId},
+    fmt::{self, Debug, Formatter},
+    hash::Hash,
+    marker::PhantomData,
+};
+
+use hashbrown::{
+    hash_map::{self, DefaultHashBuilder},
+    HashMap,
+};
+
+use crate::{cell::ResourceCell, changes::Changes, Resource};
+
+/// The slot of an entry, which lazily creates the map for its type.
+pub(crate) enum Slot<'a, K> {
+    /// The world already contains a map for the type.
+    Map(hash_map::Entry<'a, K, ResourceCell, DefaultHashBuilder>),
+    /// There is no map for the type yet.
+    NoMap(
+        hash_map::VacantEntry<'a, TypeId, HashMap<K, ResourceCell>, DefaultHashBuilder>,
+        K,
+    ),
+}
+
+/// A view into a single resource slot of a `World`, which may either be
+/// occupied or vacant. Returned by `World::entry`.
+///
+/// The world is only modified, and changes are only recorded, once a
+/// resource is inserted or accessed mutably through the entry.
+pub struct Entry<'a, K: 'a, T> {
+    slot: Slot<'a, K>,
+    changes: &'a mut Changes<K>,
+    type_names: &'a mut HashMap<TypeId, &'static str>,
+    marker: PhantomData<T>,
+}
+
+impl<'a, K, T> Entry<'a, K, T>
+where
+    K: Hash + Eq,
+    T: Resource,
+{
+    pub(crate) fn new(
+        slot: Slot<'a, K>,
+        changes: &'a mut Changes<K>,
+        type_names: &'a mut HashMap<TypeId, &'static str>,
+    ) -> Self {
+        Entry {
+            slot,
+            changes,
+            type_names,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the key of this entry.
+    pub fn key(&self) -> &K {
+        match self.slot {
+            Slot::Map(ref entry) => entry.key(),
+            Slot::NoMap(_, ref k) => k,
+        }
+    }
+
+    /// Inserts `default` if the entry is vacant, and returns a mutable
+    /// reference to the resource.
+    pub fn or_insert(self, default: T) -> &'a mut T {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is vacant, and returns a
+    /// mutable reference to the resource.
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut T
+    where
+        F: FnOnce() -> T,
+    {
+        let Entry {
+            slot,
+            changes,
+            type_names,
+            ..
+        } = self;
+        let tick = changes.next_tick();
+        let new_cell = || ResourceCell::new(Box::new(default()), tick);
+
+        let cell = match slot {
+            Slot::Map(hash_map::Entry::Occupied(entry)) => {
+                let cell = entry.into_mut();
+                cell.mark_changed(tick);
+
+                cell
+            }
+            Slot::Map(hash_map::Entry::Vacant(entry)) => {
+                Self::inserted(changes, type_names, entry.key());
+
+                entry.insert(new_cell())
+            }
+            Slot::NoMap(entry, k) => {
+                Self::inserted(changes, type_names, &k);
+
+                entry.insert(HashMap::new()).entry(k).or_insert(new_cell())
+            }
+        };
+
+        cell.get_mut().downcast_mut().expect("Unreachable")
+    }
+
+    /// Inserts `T::default()` if the entry is vacant, and returns a mutable
+    /// reference to the resource.
+    pub fn or_default(self) -> &'a mut T
+    where
+        T: Default,
+    {
+        self.or_insert_with(T::default)
+    }
+
+    /// Calls `f` with the resource if the entry is occupied.
+    pub fn and_modify<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut T),
+    {
+        let Entry {
+            slot,
+            changes,
+            type_names,
+            marker,
+        } = self;
+
+        let slot = match slot {
+            Slot::Map(entry) => Slot::Map(entry.and_modify(|cell| {
+                cell.mark_changed(changes.next_tick());
+                f(cell.get_mut().downcast_mut().expect("Unreachable"))
+            })),
+            slot => slot,
+        };
+
+        Entry {
+            slot,
+            changes,
+            type_names,
+            marker,
+        }
+    }
+
+    /// Records the insertion of a resource under `k`.
+    fn inserted(changes: &mut Changes<K>, type_names: &mut HashMap<TypeId, &'static str>, k: &K) {
+        type_names.insert(TypeId::of::<T>(), type_name::<T>());
+        changes.inserted(TypeId::of::<T>(), k);
+    }
+}
+
+impl<K: Debug, T> Debug for Entry<'_, K, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (key, state) = match self.slot {
+            Slot::Map(hash_map::Entry::Occupied(ref entry)) => (entry.key(), "Occupied"),
+            Slot::Map(hash_map::Entry::Vacant(ref entry)) => (entry.key(), "Vacant"),
+            Slot::NoMap(_, ref k) => (k, "Vacant"),
+        };
+
+        f.debug_struct("Entry")
+            .field("key", key)
+            .field("state", &state)
+            .finish()
+    }
+}
@@ -0,0 +1,153 @@
+//! Error types of this crate.
+
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+};
+
+#[cfg(feature = "sync")]
+use nitric_lock::PoisonError;
+
+/// Error returned when fetching a resource from a `World` fails.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FetchError {
+    /// There is no resource of this type with the requested key.
+    Missing {
+        /// The name of the resource type.
+        type_name: &'static str,
+    },
+    /// The resource is borrowed immutably, so it cannot be borrowed mutably.
+    Borrowed {
+        /// The name of the resource type.
+        type_name: &'static str,
+    },
+    /// The resource is borrowed mutably, so it cannot be borrowed at all.
+    BorrowedMut {
+        /// The name of the resource type.
+        type_name: &'static str,
+    },
+}
+
+impl FetchError {
+    /// Returns the name of the resource type that could not be fetched.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            FetchError::Missing { type_name }
+            | FetchError::Borrowed { type_name }
+            | FetchError::BorrowedMut { type_name } => type_name,
+        }
+    }
+}
+
+impl Display for FetchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            FetchError::Missing { type_name } => {
+                write!(f, "Resource `{}` does not exist", type_name)
+            }
+            FetchError::Borrowed { type_name } => write!(
+                f,
+                "Resource `{}` is already borrowed, cannot borrow it mutably",
+                type_name
+            ),
+            FetchError::BorrowedMut { type_name } => {
+                write!(f, "Resource `{}` is already borrowed mutably", type_name)
+            }
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+/// Error returned by `World::snapshot`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// A resource type is not registered as `Clone` (see
+    /// `Register::with_clone`).
+    NotCloneable {
+        /// The name of the resource type.
+        type_name: &'static str,
+    },
+    /// A resource could not be borrowed for cloning.
+    Fetch(FetchError),
+}
+
+impl From<FetchError> for SnapshotError {
+    fn from(e: FetchError) -> Self {
+        SnapshotError::Fetch(e)
+    }
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            SnapshotError::NotCloneable { type_name } => {
+                write!(f, "Resource `{}` is not registered as `Clone`", type_name)
+            }
+            SnapshotError::Fetch(ref e) => Display::fmt(e, f),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            SnapshotError::NotCloneable { .. } => None,
+            SnapshotError::Fetch(ref e) => Some(e),
+        }
+    }
+}
+
+/// Error returned by `SyncWorld::read` and `SyncWorld::write`.
+#[cfg(feature = "sync")]
+pub enum LockError<G> {
+    /// The resource does not exist.
+    Fetch(FetchError),
+    /// The lock of the resource is poisoned. The error still carries the
+    /// guard.
+    Poisoned(PoisonError<G>),
+}
+
+#[cfg(feature = "sync")]
+impl<G> From<FetchError> for LockError<G> {
+    fn from(e: FetchError) -> Self {
+        LockError::Fetch(e)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<G> From<PoisonError<G>> for LockError<G> {
+    fn from(e: PoisonError<G>) -> Self {
+        LockError::Poisoned(e)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<G> Debug for LockError<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            LockError::Fetch(ref e) => f.debug_tuple("Fetch").field(e).finish(),
+            LockError::Poisoned(ref e) => f.debug_tuple("Poisoned").field(e).finish(),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<G> Display for LockError<G> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            LockError::Fetch(ref e) => Display::fmt(e, f),
+            LockError::Poisoned(ref e) => Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<G> Error for LockError<G> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LockError::Fetch(ref e) => Some(e),
+            LockError::Poisoned(_) => None,
+        }
+    }
+}
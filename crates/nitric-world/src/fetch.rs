@@ -0,0 +1,287 @@
+//! Fetching multiple resources at once.
+//!
+//! `FetchMany` fetches plain references given a key for each of them, while
+//! `Fetch` describes the data a system needs, using `Read`, `Write` and their
+//! keyed variants. `Fetch` can also be derived for structs with the `derive`
+//! feature.
+
+use std::{
+    fmt::{self, Debug, Formatter},
+    hash::Hash,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{FetchError, Ref, RefMut, Resource, World};
+
+/// Describes a set of resources that can be fetched at once with
+/// `World::fetch_many`, implemented for `&T`, `&mut T` and tuples of those.
+pub trait FetchMany<'a, K>
+where
+    K: Hash + Eq,
+{
+    /// The keys needed to look up the resources.
+    type Keys;
+    /// The guards of the fetched resources.
+    type Output;
+
+    /// Fetches the resources from `world`.
+    fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self::Output, FetchError>;
+}
+
+impl<'a, 'k, K, T> FetchMany<'a, K> for &'k T
+where
+    K: Hash + Eq + 'k,
+    T: Resource,
+{
+    type Keys = &'k K;
+    type Output = Ref<'a, T>;
+
+    fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self::Output, FetchError> {
+        world.try_fetch(keys)
+    }
+}
+
+impl<'a, 'k, K, T> FetchMany<'a, K> for &'k mut T
+where
+    K: Hash + Eq + 'k,
+    T: Resource,
+{
+    type Keys = &'k K;
+    type Output = RefMut<'a, T>;
+
+    fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self::Output, FetchError> {
+        world.try_fetch_mut(keys)
+    }
+}
+
+macro_rules! impl_fetch_many {
+    ($($ty:ident $idx:tt),+) => {
+        impl<'a, K, $($ty),+> FetchMany<'a, K> for ($($ty,)+)
+        where
+            K: Hash + Eq,
+            $($ty: FetchMany<'a, K>,)+
+        {
+            type Keys = ($($ty::Keys,)+);
+            type Output = ($($ty::Output,)+);
+
+            fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self::Output, FetchError> {
+                Ok(($($ty::fetch(world, keys.$idx)?,)+))
+            }
+        }
+    };
+}
+
+impl_fetch_many!(A 0);
+impl_fetch_many!(A 0, B 1);
+impl_fetch_many!(A 0, B 1, C 2);
+impl_fetch_many!(A 0, B 1, C 2, D 3);
+impl_fetch_many!(A 0, B 1, C 2, D 3, E 4);
+impl_fetch_many!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_fetch_many!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_fetch_many!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// Data that can be fetched from a `World` at once, with `World::fetch_data`.
+///
+/// Implemented for `Read`, `Write`, `ReadKey`, `WriteKey`, `Option`s of those
+/// and tuples. With the `derive` feature, it can be derived for structs whose
+/// fields all implement `Fetch`:
+///
+/// ```
+/// # #[cfg(feature = "derive")]
+/// # {
+/// use nitric_world::{Fetch, Read, World, Write};
+///
+/// #[derive(Fetch)]
+/// struct Movement<'a> {
+///     time: Read<'a, f32>,
+///     pos: Write<'a, Vec<f32>>,
+/// }
+///
+/// let mut world = World::<&str>::new();
+/// world.insert("", 0.5f32);
+/// world.insert("", vec![1.0f32, 2.0]);
+///
+/// let mut data = world.fetch_data::<Movement>(Default::default());
+/// for pos in data.pos.iter_mut() {
+///     *pos += *data.time;
+/// }
+/// # }
+/// ```
+pub trait Fetch<'a, K>: Sized
+where
+    K: Hash + Eq,
+{
+    /// The keys needed to look up the resources; `()` for resources stored
+    /// under the default key.
+    type Keys;
+
+    /// Fetches the data from `world`.
+    fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self, FetchError>;
+}
+
+/// Shared access to the resource of type `T` stored under the default key
+/// (`K::default()`).
+pub struct Read<'a, T> {
+    inner: Ref<'a, T>,
+}
+
+/// Mutable access to the resource of type `T` stored under the default key
+/// (`K::default()`).
+pub struct Write<'a, T> {
+    inner: RefMut<'a, T>,
+}
+
+/// Shared access to a resource of type `T` stored under a key given at fetch
+/// time.
+pub struct ReadKey<'a, T> {
+    inner: Ref<'a, T>,
+}
+
+/// Mutable access to a resource of type `T` stored under a key given at fetch
+/// time.
+pub struct WriteKey<'a, T> {
+    inner: RefMut<'a, T>,
+}
+
+macro_rules! impl_wrapper {
+    ($name:ident, $guard:ident) => {
+        impl<T> Deref for $name<'_, T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.inner
+            }
+        }
+
+        impl<T: Debug> Debug for $name<'_, T> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                Debug::fmt(&*self.inner, f)
+            }
+        }
+
+        impl<'a, T> From<$guard<'a, T>> for $name<'a, T> {
+            fn from(inner: $guard<'a, T>) -> Self {
+                $name { inner }
+            }
+        }
+    };
+}
+
+impl_wrapper!(Read, Ref);
+impl_wrapper!(Write, RefMut);
+impl_wrapper!(ReadKey, Ref);
+impl_wrapper!(WriteKey, RefMut);
+
+impl<T> DerefMut for Write<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T> DerefMut for WriteKey<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<'a, K, T> Fetch<'a, K> for Read<'a, T>
+where
+    K: Hash + Eq + Default,
+    T: Resource,
+{
+    type Keys = ();
+
+    fn fetch(world: &'a World<K>, (): ()) -> Result<Self, FetchError> {
+        world.try_fetch(&K::default()).map(From::from)
+    }
+}
+
+impl<'a, K, T> Fetch<'a, K> for Write<'a, T>
+where
+    K: Hash + Eq + Default,
+    T: Resource,
+{
+    type Keys = ();
+
+    fn fetch(world: &'a World<K>, (): ()) -> Result<Self, FetchError> {
+        world.try_fetch_mut(&K::default()).map(From::from)
+    }
+}
+
+impl<'a, K, T> Fetch<'a, K> for ReadKey<'a, T>
+where
+    K: Hash + Eq,
+    T: Resource,
+{
+    type Keys = K;
+
+    fn fetch(world: &'a World<K>, key: K) -> Result<Self, FetchError> {
+        world.try_fetch(&key).map(From::from)
+    }
+}
+
+impl<'a, K, T> Fetch<'a, K> for WriteKey<'a, T>
+where
+    K: Hash + Eq,
+    T: Resource,
+{
+    type Keys = K;
+
+    fn fetch(world: &'a World<K>, key: K) -> Result<Self, FetchError> {
+        world.try_fetch_mut(&key).map(From::from)
+    }
+}
+
+/// Fetches `F` if all of its resources exist, and `None` otherwise. Borrow
+/// conflicts are still reported as errors.
+impl<'a, K, F> Fetch<'a, K> for Option<F>
+where
+    K: Hash + Eq,
+    F: Fetch<'a, K>,
+{
+    type Keys = F::Keys;
+
+    fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self, FetchError> {
+        match F::fetch(world, keys) {
+            Ok(data) => Ok(Some(data)),
+            Err(FetchError::Missing { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<'a, K> Fetch<'a, K> for ()
+where
+    K: Hash + Eq,
+{
+    type Keys = ();
+
+    fn fetch(_: &'a World<K>, (): ()) -> Result<Self, FetchError> {
+        Ok(())
+    }
+}
+
+macro_rules! impl_fetch {
+    ($($ty:ident $idx:tt),+) => {
+        impl<'a, K, $($ty),+> Fetch<'a, K> for ($($ty,)+)
+        where
+            K: Hash + Eq,
+            $($ty: Fetch<'a, K>,)+
+        {
+            type Keys = ($($ty::Keys,)+);
+
+            fn fetch(world: &'a World<K>, keys: Self::Keys) -> Result<Self, FetchError> {
+                Ok(($($ty::fetch(world, keys.$idx)?,)+))
+            }
+        }
+    };
+}
+
+impl_fetch!(A 0);
+impl_fetch!(A 0, B 1);
+impl_fetch!(A 0, B 1, C 2);
+impl_fetch!(A 0, B 1, C 2, D 3);
+impl_fetch!(A 0, B 1, C 2, D 3, E 4);
+impl_fetch!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_fetch!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_fetch!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
@@ -2,43 +2,245 @@
 
 //! Provides a `World` type which allows you to index resources with their type,
 //! and an arbitrary key type.
+//!
+//! ## Borrowing
+//!
+//! Besides the plain `get` / `get_mut` accessors, resources can be fetched
+//! through a shared reference to the `World` with `fetch` and `fetch_mut`.
+//! These return guards and check at runtime that a resource is never borrowed
+//! mutably while it is borrowed elsewhere, so a system can borrow many
+//! resources at the same time (see `fetch_many`).
+//!
+//! Systems can describe the data they need with a type implementing `Fetch`,
+//! such as `(Read<Time>, Write<Positions>)`, and get all of it with a single
+//! call to `fetch_data`.
+//!
+//! ## Layering
+//!
+//! A world created with `World::child` falls back to its parent for every
+//! resource it does not contain itself, so several worlds can share common
+//! resources while overriding some of them locally.
+//!
+//! ## Change tracking
+//!
+//! After calling `track_changes`, the world remembers when each resource was
+//! last inserted, accessed mutably or removed, which can be queried with
+//! `changed_since` and `changes_since`.
+//!
+//! ## Claiming
+//!
+//! A borrow cannot outlive the reference to the world it was fetched
+//! through. Resources which have to stay reserved for longer, e.g. while an
+//! asynchronous system runs across several frames, can be reserved with
+//! `claim` instead. Code which entered the `Claim` can still borrow them,
+//! while everything else fails as if they were borrowed.
+//!
+//! ## Dynamic access
+//!
+//! Types registered in the world's `TypeRegistry` can be accessed by name,
+//! without knowing their static type (see `get_dyn` and `insert_dyn`), which
+//! is useful for scripting and tooling.
+//!
+//! Resources of types registered as `Clone` can be copied into a `Snapshot`
+//! with `snapshot`, and put back with `restore`, e.g. to implement undo.
+//!
+//! ## Features
+//!
+//! * `derive`: Adds `#[derive(Fetch)]` for structs whose fields all implement
+//!   `Fetch`.
+//! * `serde`: Allows types to be registered with serde support, so a `World`
+//!   can be serialized as a map from type names to keys to values.
+//! * `sync`: Adds `SyncWorld`, which stores every resource in a lock of
+//!   `nitric-lock`, allowing concurrent and deadlock-free access.
 
-use std::{any::TypeId, borrow::Borrow, hash::Hash};
+pub use self::{
+    cell::{Ref, RefMut},
+    changes::Change,
+    claim::{key_hash, Claim},
+    entry::Entry,
+    error::{FetchError, SnapshotError},
+    fetch::{Fetch, FetchMany, Read, ReadKey, Write, WriteKey},
+    registry::{Register, Registration, TypeKey, TypeRegistry, Unregistered},
+    snapshot::Snapshot,
+};
+
+#[cfg(feature = "derive")]
+pub use nitric_world_derive::Fetch;
+
+#[cfg(feature = "serde")]
+pub use self::serialize::SerializeWorld;
+#[cfg(feature = "sync")]
+pub use self::{error::LockError, sync::SyncWorld};
+
+use std::{
+    any::{type_name, TypeId},
+    borrow::Borrow,
+    hash::Hash,
+    sync::Arc,
+};
 
 use derive_new::new;
-use hashbrown::HashMap;
+use hashbrown::{hash_map, HashMap};
 use mopa::{mopafy, Any};
 
+use self::{cell::ResourceCell, changes::Changes, entry::Slot};
+
+// Allows the code generated by `#[derive(Fetch)]` to be used in this crate.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as nitric_world;
+
+mod cell;
+mod changes;
+mod claim;
+mod entry;
+mod error;
+mod fetch;
+mod registry;
+#[cfg(feature = "serde")]
+mod serialize;
+mod snapshot;
+#[cfg(feature = "sync")]
+mod sync;
+
 /// A collection of resources that can be accessed via their Type and an
 /// arbitrary key type `K`.
-#[derive(Default, new)]
+#[derive(new)]
 pub struct World<K>
 where
     K: Hash + Eq,
 {
     #[new(default)]
-    resources: HashMap<TypeId, HashMap<K, Box<Resource>>>,
+    resources: HashMap<TypeId, HashMap<K, ResourceCell>>,
+    /// The names of all types in `resources`.
+    #[new(default)]
+    type_names: HashMap<TypeId, &'static str>,
+    #[new(default)]
+    registry: TypeRegistry,
+    /// The world to fall back to for resources missing in this one.
+    #[new(default)]
+    parent: Option<Arc<World<K>>>,
+    #[new(default)]
+    changes: Changes<K>,
+}
+
+impl<K: Hash + Eq> Default for World<K> {
+    fn default() -> Self {
+        World::new()
+    }
 }
 
 impl<K: Hash + Eq> World<K> {
+    /// Creates an empty world layered on top of `parent`.
+    ///
+    /// Shared lookups (`get`, `fetch`, `try_fetch`, `contains`, `get_dyn` and
+    /// everything built on them, like `Read`) fall through to the parent if
+    /// the resource does not exist in the child. Everything else, including
+    /// insertion, mutable access, iteration and serialization, only concerns
+    /// the child's own resources.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use nitric_world::World;
+    ///
+    /// let mut config = World::new();
+    /// config.insert("gravity", 9.81f32);
+    /// config.insert("friction", 0.5f32);
+    /// let config = Arc::new(config);
+    ///
+    /// let mut moon = World::child(config.clone());
+    /// moon.insert("gravity", 1.62f32);
+    ///
+    /// assert_eq!(moon.get::<f32, _>("gravity").as_deref(), Some(&1.62));
+    /// assert_eq!(moon.get::<f32, _>("friction").as_deref(), Some(&0.5));
+    /// assert_eq!(config.get::<f32, _>("gravity").as_deref(), Some(&9.81));
+    /// ```
+    pub fn child(parent: Arc<World<K>>) -> Self {
+        World {
+            parent: Some(parent),
+            ..World::new()
+        }
+    }
+
+    /// Returns the parent of this world, if it was created with `child`.
+    pub fn parent(&self) -> Option<&Arc<World<K>>> {
+        self.parent.as_ref()
+    }
+
     /// Adds a resource to the world
     pub fn insert<T: Resource>(&mut self, k: K, v: T) -> Option<T> {
+        self.type_names.insert(TypeId::of::<T>(), type_name::<T>());
+        self.changes.inserted(TypeId::of::<T>(), &k);
+        let tick = self.changes.next_tick();
+
         self.resources
             .entry(TypeId::of::<T>())
-            .or_insert_with(|| HashMap::new())
-            .insert(k, Box::new(v) as Box<Resource>)
-            .map(|b| *b.downcast().ok().expect("Unreachable"))
+            .or_default()
+            .insert(k, ResourceCell::new(Box::new(v), tick))
+            .map(|cell| *cell.into_inner().downcast().ok().expect("Unreachable"))
     }
 
-    /// Retrieves an immutable reference to a resource from the world.
-    pub fn get<T: Resource, Q: ?Sized>(&self, k: &Q) -> Option<&T>
+    /// Gets the entry of the resource of type `T` under the key `k`, for
+    /// in-place insertion and manipulation.
+    ///
+    /// ```
+    /// use nitric_world::World;
+    ///
+    /// let mut world = World::new();
+    /// *world.entry::<u32>("count").or_insert(0) += 1;
+    /// world.entry::<u32>("count").and_modify(|c| *c += 1).or_insert(0);
+    ///
+    /// assert_eq!(world.get::<u32, _>("count").as_deref(), Some(&2));
+    /// ```
+    pub fn entry<T: Resource>(&mut self, k: K) -> Entry<'_, K, T> {
+        let slot = match self.resources.entry(TypeId::of::<T>()) {
+            hash_map::Entry::Occupied(map) => Slot::Map(map.into_mut().entry(k)),
+            hash_map::Entry::Vacant(entry) => Slot::NoMap(entry, k),
+        };
+
+        Entry::new(slot, &mut self.changes, &mut self.type_names)
+    }
+
+    /// Retrieves a mutable reference to a resource, inserting `T::default()`
+    /// first if it does not exist.
+    pub fn get_or_insert_default<T: Resource + Default>(&mut self, k: K) -> &mut T {
+        self.entry(k).or_default()
+    }
+
+    /// Borrows a resource immutably from the world, or from its parent if
+    /// the world does not contain it. Returns `None` if neither contains the
+    /// resource.
+    ///
+    /// The borrow is tracked like one returned by `fetch`, so the resource
+    /// can be fetched mutably again once the returned `Ref` is dropped.
+    ///
+    /// ```
+    /// use nitric_world::World;
+    ///
+    /// let mut world = World::new();
+    /// world.insert("a", 1u32);
+    ///
+    /// assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&1));
+    /// *world.fetch_mut::<u32, _>("a") += 1;
+    /// assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&2));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource is currently fetched mutably.
+    pub fn get<T: Resource, Q: ?Sized>(&self, k: &Q) -> Option<Ref<'_, T>>
     where
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
-        self.resources
-            .get(&TypeId::of::<T>())
-            .and_then(|m| m.get(k).and_then(|b| b.downcast_ref()))
+        match self.cell::<T, Q>(k) {
+            Some(cell) => Some(
+                cell.borrow(type_name::<T>())
+                    .map(Ref::downcast)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+            ),
+            None => self.parent.as_ref()?.get::<T, Q>(k),
+        }
     }
 
     /// Retrieves a mutable reference to a resource from the world.
@@ -47,9 +249,151 @@ impl<K: Hash + Eq> World<K> {
         K: Borrow<Q>,
         Q: Hash + Eq,
     {
+        let tick = self.changes.next_tick();
+
         self.resources
             .get_mut(&TypeId::of::<T>())
-            .and_then(|m| m.get_mut(k).and_then(|b| b.downcast_mut()))
+            .and_then(|m| m.get_mut(k))
+            .and_then(|cell| {
+                cell.mark_changed(tick);
+
+                cell.get_mut().downcast_mut()
+            })
+    }
+
+    /// Borrows a resource immutably, tracking the borrow at runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource does not exist or is borrowed mutably. See
+    /// `try_fetch` for a non-panicking version.
+    pub fn fetch<T: Resource, Q>(&self, k: &Q) -> Ref<'_, T>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.try_fetch(k).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Borrows a resource mutably, tracking the borrow at runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resource does not exist or is borrowed already. See
+    /// `try_fetch_mut` for a non-panicking version.
+    pub fn fetch_mut<T: Resource, Q>(&self, k: &Q) -> RefMut<'_, T>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.try_fetch_mut(k).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Borrows a resource immutably, failing if it does not exist or is
+    /// borrowed mutably. Falls back to the parent if the world does not
+    /// contain the resource.
+    pub fn try_fetch<T: Resource, Q>(&self, k: &Q) -> Result<Ref<'_, T>, FetchError>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let type_name = type_name::<T>();
+
+        match (self.cell::<T, Q>(k), &self.parent) {
+            (Some(cell), _) => cell.borrow(type_name).map(Ref::downcast),
+            (None, Some(parent)) => parent.try_fetch(k),
+            (None, None) => Err(FetchError::Missing { type_name }),
+        }
+    }
+
+    /// Borrows a resource mutably, failing if it does not exist or is
+    /// borrowed already. Resources of the parent cannot be borrowed mutably.
+    pub fn try_fetch_mut<T: Resource, Q>(&self, k: &Q) -> Result<RefMut<'_, T>, FetchError>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let type_name = type_name::<T>();
+
+        let cell = self
+            .cell::<T, Q>(k)
+            .ok_or(FetchError::Missing { type_name })?;
+        let borrow = cell.borrow_mut(type_name)?;
+        cell.mark_changed(self.changes.next_tick());
+
+        Ok(RefMut::downcast(borrow))
+    }
+
+    /// Fetches multiple resources at once, described by `F`, which is a
+    /// tuple of `&T` and `&mut T`. `keys` is a tuple with one key for each
+    /// resource.
+    ///
+    /// ```
+    /// use nitric_world::World;
+    ///
+    /// let mut world = World::new();
+    /// world.insert("a", 1u32);
+    /// world.insert("b", 2u64);
+    ///
+    /// let (a, mut b) = world.fetch_many::<(&u32, &mut u64)>((&"a", &"b"));
+    /// *b += u64::from(*a);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the resources does not exist or cannot be borrowed,
+    /// including if `F` borrows a resource mutably twice.
+    pub fn fetch_many<'a, F>(&'a self, keys: F::Keys) -> F::Output
+    where
+        F: FetchMany<'a, K>,
+    {
+        self.try_fetch_many::<F>(keys)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `fetch_many`, but returns an error instead of panicking.
+    pub fn try_fetch_many<'a, F>(&'a self, keys: F::Keys) -> Result<F::Output, FetchError>
+    where
+        F: FetchMany<'a, K>,
+    {
+        F::fetch(self, keys)
+    }
+
+    /// Fetches the data described by `F`, e.g. a tuple of `Read`s and
+    /// `Write`s. `keys` holds the keys of the keyed variants; it is `()` for
+    /// data stored under the default key only.
+    ///
+    /// ```
+    /// use nitric_world::{Read, ReadKey, World, Write};
+    ///
+    /// let mut world = World::<&str>::new();
+    /// world.insert("", 0.5f32);
+    /// world.insert("", 1.0f64);
+    /// world.insert("speed", 3.0f32);
+    ///
+    /// let (dt, speed, mut pos) =
+    ///     world.fetch_data::<(Read<f32>, ReadKey<f32>, Write<f64>)>(((), "speed", ()));
+    /// *pos += f64::from(*dt * *speed);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if a resource required by `F` does not exist or cannot be
+    /// borrowed. See `try_fetch_data` for a non-panicking version.
+    pub fn fetch_data<'a, F>(&'a self, keys: F::Keys) -> F
+    where
+        F: Fetch<'a, K>,
+    {
+        self.try_fetch_data(keys)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `fetch_data`, but returns an error instead of panicking.
+    pub fn try_fetch_data<'a, F>(&'a self, keys: F::Keys) -> Result<F, FetchError>
+    where
+        F: Fetch<'a, K>,
+    {
+        F::fetch(self, keys)
     }
 
     /// Removes a resource from the world.
@@ -59,27 +403,470 @@ impl<K: Hash + Eq> World<K> {
         Q: Hash + Eq,
     {
         let mut kill_it = false;
+        let changes = &mut self.changes;
         let ret = self.resources.get_mut(&TypeId::of::<T>()).and_then(|m| {
-            let ret = m
-                .remove(k)
-                .map(|b| *b.downcast().ok().expect("Unreachable"));
+            let ret = m.remove_entry(k).map(|(k, cell)| {
+                changes.removed(TypeId::of::<T>(), type_name::<T>(), k);
+
+                *cell.into_inner().downcast().ok().expect("Unreachable")
+            });
             kill_it = m.len() == 0;
             ret
         });
         if kill_it {
             self.resources.remove(&TypeId::of::<T>());
+            self.type_names.remove(&TypeId::of::<T>());
+        }
+        ret
+    }
+
+    /// Returns the registry of types that can be accessed dynamically.
+    pub fn registry(&self) -> &TypeRegistry {
+        &self.registry
+    }
+
+    /// Returns the registry of types that can be accessed dynamically,
+    /// mutably.
+    pub fn registry_mut(&mut self) -> &mut TypeRegistry {
+        &mut self.registry
+    }
+
+    /// Registers `T` under `name` in the world's registry. See
+    /// `TypeRegistry::register`.
+    pub fn register<T: Resource>(&mut self, name: impl Into<String>) -> Register<'_, T> {
+        self.registry.register(name)
+    }
+
+    /// Retrieves a resource without knowing its type. `ty` is either a
+    /// `TypeId` or the name of a registered type.
+    ///
+    /// ```
+    /// use nitric_world::World;
+    ///
+    /// let mut world = World::new();
+    /// world.register::<f32>("gravity").with_debug();
+    /// world.insert("earth", 9.81f32);
+    ///
+    /// let gravity = world.get_dyn("gravity", "earth").unwrap();
+    /// let registration = world.registry().get("gravity").unwrap();
+    ///
+    /// assert_eq!(format!("{:?}", registration.debug(&*gravity).unwrap()), "9.81");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Like `get`, this panics if the resource is currently fetched mutably.
+    pub fn get_dyn<'t, Q>(&self, ty: impl Into<TypeKey<'t>>, k: &Q) -> Option<Ref<'_, dyn Resource>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let ty = ty.into();
+        let cell = self
+            .registry
+            .resolve(ty)
+            .and_then(|id| Some((id, self.resources.get(&id)?.get(k)?)));
+
+        match cell {
+            Some((id, cell)) => Some(
+                cell.borrow(self.type_names[&id])
+                    .unwrap_or_else(|e| panic!("{}", e)),
+            ),
+            None => self.parent.as_ref()?.get_dyn(ty, k),
         }
+    }
+
+    /// Retrieves a resource mutably without knowing its type. `ty` is either
+    /// a `TypeId` or the name of a registered type.
+    pub fn get_dyn_mut<'t, Q>(
+        &mut self,
+        ty: impl Into<TypeKey<'t>>,
+        k: &Q,
+    ) -> Option<&mut dyn Resource>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let id = self.registry.resolve(ty)?;
+        let tick = self.changes.next_tick();
+
+        self.resources
+            .get_mut(&id)
+            .and_then(|m| m.get_mut(k))
+            .map(|cell| {
+                cell.mark_changed(tick);
+
+                cell.get_mut()
+            })
+    }
+
+    /// Adds a boxed resource to the world, which is stored under its dynamic
+    /// type. Returns the resource previously stored under the same type and
+    /// key, if any.
+    pub fn insert_dyn(&mut self, k: K, v: Box<dyn Resource>) -> Option<Box<dyn Resource>> {
+        let id = (*v).get_type_id();
+        self.type_names.insert(id, (*v).type_name());
+        self.changes.inserted(id, &k);
+        let tick = self.changes.next_tick();
+
+        self.resources
+            .entry(id)
+            .or_default()
+            .insert(k, ResourceCell::new(v, tick))
+            .map(ResourceCell::into_inner)
+    }
+
+    /// Removes a resource without knowing its type. `ty` is either a `TypeId`
+    /// or the name of a registered type.
+    pub fn remove_dyn<'t, Q>(
+        &mut self,
+        ty: impl Into<TypeKey<'t>>,
+        k: &Q,
+    ) -> Option<Box<dyn Resource>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let id = self.registry.resolve(ty)?;
+        let map = self.resources.get_mut(&id)?;
+        let (changes, type_name) = (&mut self.changes, self.type_names[&id]);
+        let ret = map.remove_entry(k).map(|(k, cell)| {
+            changes.removed(id, type_name, k);
+
+            cell.into_inner()
+        });
+
+        if map.is_empty() {
+            self.resources.remove(&id);
+            self.type_names.remove(&id);
+        }
+
         ret
     }
+
+    /// Removes all resources of type `T`, returning them together with their
+    /// keys. The keys are cloned to record the removals if changes are
+    /// tracked.
+    pub fn drain<T: Resource>(&mut self) -> impl Iterator<Item = (K, T)>
+    where
+        K: Clone,
+    {
+        self.type_names.remove(&TypeId::of::<T>());
+
+        let resources = self
+            .resources
+            .remove(&TypeId::of::<T>())
+            .unwrap_or_default();
+        if self.changes.is_enabled() {
+            for k in resources.keys() {
+                self.changes
+                    .removed(TypeId::of::<T>(), type_name::<T>(), k.clone());
+            }
+        }
+
+        resources
+            .into_iter()
+            .map(|(k, cell)| (k, *cell.into_inner().downcast().ok().expect("Unreachable")))
+    }
+
+    /// Checks if the world or its parent contains a resource of type `T`
+    /// under the key `k`.
+    pub fn contains<T: Resource, Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.cell::<T, Q>(k).is_some()
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.contains::<T, Q>(k))
+    }
+
+    /// Returns the keys of all resources of type `T`.
+    pub fn keys<T: Resource>(&self) -> impl Iterator<Item = &K> {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .into_iter()
+            .flat_map(|m| m.keys())
+    }
+
+    /// Iterates over all resources of type `T` together with their keys.
+    ///
+    /// Like `get`, every resource is borrowed until its `Ref` is dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics when reaching a resource that is currently fetched mutably.
+    pub fn iter<T: Resource>(&self) -> impl Iterator<Item = (&K, Ref<'_, T>)> {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .into_iter()
+            .flat_map(|m| m.iter())
+            .map(move |(k, cell)| {
+                let value = cell
+                    .borrow(type_name::<T>())
+                    .map(Ref::downcast)
+                    .unwrap_or_else(|e| panic!("{}", e));
+
+                (k, value)
+            })
+    }
+
+    /// Iterates mutably over all resources of type `T` together with their
+    /// keys.
+    pub fn iter_mut<T: Resource>(&mut self) -> impl Iterator<Item = (&K, &mut T)> {
+        let tick = self.changes.next_tick();
+
+        self.resources
+            .get_mut(&TypeId::of::<T>())
+            .into_iter()
+            .flat_map(|m| m.iter_mut())
+            .map(move |(k, cell)| {
+                cell.mark_changed(tick);
+
+                (k, cell.get_mut().downcast_mut().expect("Unreachable"))
+            })
+    }
+
+    /// Returns the total number of resources in the world.
+    pub fn len(&self) -> usize {
+        self.resources.values().map(HashMap::len).sum()
+    }
+
+    /// Checks if the world contains no resources at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lists the types of all resources in the world, together with their
+    /// names.
+    pub fn types(&self) -> impl Iterator<Item = (TypeId, &'static str)> + '_ {
+        self.resources
+            .iter()
+            .map(move |(id, _)| (*id, self.type_names[id]))
+    }
+
+    /// Enables change tracking. From now on, every insertion, mutable access
+    /// and removal of a resource is recorded with a new change tick.
+    /// Resources which already exist are considered changed at the current
+    /// tick.
+    ///
+    /// Changes are tracked per world; changes in the parent of a child world
+    /// are not reported by the child.
+    ///
+    /// ```
+    /// use nitric_world::World;
+    ///
+    /// let mut world = World::new();
+    /// world.track_changes();
+    /// world.insert("volume", 0.5f32);
+    ///
+    /// let seen = world.change_tick();
+    /// assert!(!world.changed_since::<f32, _>("volume", seen));
+    ///
+    /// *world.get_mut::<f32, _>("volume").unwrap() = 0.8;
+    /// assert!(world.changed_since::<f32, _>("volume", seen));
+    /// ```
+    pub fn track_changes(&mut self) {
+        if self.changes.is_enabled() {
+            return;
+        }
+
+        let tick = self.changes.enable();
+        for cell in self.resources.values().flat_map(HashMap::values) {
+            cell.mark_changed(tick);
+        }
+    }
+
+    /// Returns the latest change tick, which can be passed to
+    /// `changed_since` and `changes_since` later on.
+    pub fn change_tick(&self) -> u64 {
+        self.changes.tick()
+    }
+
+    /// Checks if the resource of type `T` under the key `k` was inserted,
+    /// accessed mutably or removed after `tick`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if change tracking is not enabled (see `track_changes`).
+    pub fn changed_since<T: Resource, Q>(&self, k: &Q, tick: u64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.assert_tracking();
+
+        let changed = match self.cell::<T, Q>(k) {
+            Some(cell) => Some(cell.changed()),
+            None => self.changes.removed_tick(TypeId::of::<T>(), k),
+        };
+
+        changed.is_some_and(|changed| changed > tick)
+    }
+
+    /// Iterates over all resources which were inserted, accessed mutably or
+    /// removed after `tick`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if change tracking is not enabled (see `track_changes`).
+    pub fn changes_since(&self, tick: u64) -> impl Iterator<Item = Change<'_, K>> {
+        self.assert_tracking();
+
+        let changed = self.resources.iter().flat_map(move |(&type_id, m)| {
+            let type_name = self.type_names[&type_id];
+
+            m.iter()
+                .filter(move |(_, cell)| cell.changed() > tick)
+                .map(move |(key, cell)| Change {
+                    type_id,
+                    type_name,
+                    key,
+                    tick: cell.changed(),
+                    removed: false,
+                })
+        });
+
+        changed.chain(self.changes.removed_since(tick))
+    }
+
+    /// Forgets about resources removed at or before `tick`, which releases
+    /// their keys. They are no longer reported as changed.
+    pub fn clear_removed(&mut self, tick: u64) {
+        self.changes.clear_removed(tick);
+    }
+
+    /// Claims resources for longer than a borrow could last, e.g. for a run
+    /// of an asynchronous system spanning several frames. The resources are
+    /// identified by their type and the `key_hash` of their key. Resources
+    /// which do not exist are skipped, and so are the ones of the parent.
+    ///
+    /// The resources in `reads` stay borrowed immutably until the claim is
+    /// passed to `release`. The resources in `writes` cannot be borrowed at
+    /// all, except from code which entered the claim with `Claim::enter`.
+    /// Mutable access to the world is not affected by claims.
+    ///
+    /// ```
+    /// use std::any::TypeId;
+    ///
+    /// use nitric_world::{key_hash, World};
+    ///
+    /// let mut world = World::new();
+    /// world.insert("score", 0u32);
+    ///
+    /// let claim = world
+    ///     .claim(&[], &[(TypeId::of::<u32>(), key_hash("score"))])
+    ///     .unwrap();
+    /// assert!(world.try_fetch::<u32, _>("score").is_err());
+    ///
+    /// claim.enter(|| *world.fetch_mut::<u32, _>("score") += 1);
+    ///
+    /// world.release(claim);
+    /// assert_eq!(*world.fetch::<u32, _>("score"), 1);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails without claiming anything if one of the resources is borrowed
+    /// or claimed in a conflicting way.
+    pub fn claim(
+        &self,
+        reads: &[(TypeId, u64)],
+        writes: &[(TypeId, u64)],
+    ) -> Result<Claim, FetchError> {
+        let mut claim = Claim::new();
+        let resources = writes.iter().map(|id| (id, true)).chain(
+            reads
+                .iter()
+                .filter(|id| !writes.contains(id))
+                .map(|id| (id, false)),
+        );
+
+        for (&(type_id, hash), exclusive) in resources {
+            let cells = self
+                .resources
+                .get(&type_id)
+                .into_iter()
+                .flat_map(|m| m.iter())
+                .filter(|(k, _)| key_hash(*k) == hash);
+
+            for (_, cell) in cells {
+                let type_name = self.type_names[&type_id];
+                let result = match exclusive {
+                    true => cell.claim_exclusive(claim.id(), type_name),
+                    false => cell.claim_shared(type_name),
+                };
+
+                if let Err(e) = result {
+                    self.release(claim);
+
+                    return Err(e);
+                }
+                claim.push(type_id, cell.serial(), exclusive);
+            }
+        }
+
+        Ok(claim)
+    }
+
+    /// Releases the resources reserved by `claim`. Resources which have been
+    /// removed or replaced in the meantime are skipped.
+    pub fn release(&self, claim: Claim) {
+        for (type_id, serial, exclusive) in claim.into_cells() {
+            let cell = self
+                .resources
+                .get(&type_id)
+                .and_then(|m| m.values().find(|cell| cell.serial() == serial));
+
+            match (cell, exclusive) {
+                (Some(cell), true) => cell.release_exclusive(),
+                (Some(cell), false) => cell.release_shared(),
+                (None, _) => {}
+            }
+        }
+    }
+
+    fn assert_tracking(&self) {
+        assert!(
+            self.changes.is_enabled(),
+            "Change tracking is not enabled, see `World::track_changes`"
+        );
+    }
+
+    fn cell<T: Resource, Q>(&self, k: &Q) -> Option<&ResourceCell>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .and_then(|m| m.get(k))
+    }
 }
 
 /// A bundle trait automatically implemented for any type that is `Any + Send +
 /// Sync`.
-pub trait Resource: Any + Send + Sync + 'static {}
+pub trait Resource: Any + Send + Sync + 'static {
+    /// Returns the name of the concrete type, as given by
+    /// `std::any::type_name`.
+    ///
+    /// Note that calling this on a `Box<dyn Resource>` returns the name of
+    /// the box type; dereference it first.
+    fn type_name(&self) -> &'static str;
+}
 
 mopafy!(Resource);
 
-impl<T> Resource for T where T: Any + Send + Sync {}
+impl<T> Resource for T
+where
+    T: Any + Send + Sync,
+{
+    fn type_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -90,15 +877,418 @@ mod tests {
         let mut world = World::new();
         assert_eq!(world.insert::<u32>(String::from("foo"), 2), None);
         assert_eq!(world.insert::<u32>(String::from("foo"), 1), Some(2));
-        assert_eq!(world.get::<u32, _>("foo"), Some(&1));
+        assert_eq!(world.get::<u32, _>("foo").as_deref(), Some(&1));
         assert_eq!(world.get_mut::<u32, _>("foo"), Some(&mut 1));
-        assert_eq!(world.get::<u32, _>("bar"), None);
+        assert_eq!(world.get::<u32, _>("bar").as_deref(), None);
         assert_eq!(world.get_mut::<u32, _>("bar"), None);
-        assert_eq!(world.get::<i32, _>("foo"), None);
+        assert_eq!(world.get::<i32, _>("foo").as_deref(), None);
         assert_eq!(world.get_mut::<i32, _>("foo"), None);
         assert_eq!(world.remove::<i32, _>("foo"), None);
         assert_eq!(world.remove::<u32, _>("bar"), None);
         assert_eq!(world.remove::<u32, _>("foo"), Some(1));
         assert_eq!(world.remove::<u32, _>("foo"), None);
     }
+
+    #[test]
+    fn enumerate() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.insert("b", 2u32);
+        world.insert("a", 3u64);
+
+        assert_eq!(world.len(), 3);
+        assert!(world.contains::<u32, _>("b"));
+        assert!(!world.contains::<u64, _>("b"));
+
+        let mut keys: Vec<_> = world.keys::<u32>().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, ["a", "b"]);
+
+        let mut types: Vec<_> = world.types().map(|(_, name)| name).collect();
+        types.sort();
+        assert_eq!(types, ["u32", "u64"]);
+
+        for (_, v) in world.iter_mut::<u32>() {
+            *v *= 10;
+        }
+
+        let mut values: Vec<_> = world.iter::<u32>().map(|(&k, v)| (k, *v)).collect();
+        values.sort();
+        assert_eq!(values, [("a", 10), ("b", 20)]);
+        assert!(world.try_fetch_mut::<u32, _>("a").is_ok());
+
+        let mut drained: Vec<_> = world.drain::<u32>().collect();
+        drained.sort();
+        assert_eq!(drained, [("a", 10), ("b", 20)]);
+        assert_eq!(world.keys::<u32>().count(), 0);
+        assert_eq!(world.types().count(), 1);
+        assert_eq!(world.len(), 1);
+
+        world.remove::<u64, _>("a");
+        assert!(world.is_empty());
+        assert_eq!(world.types().count(), 0);
+    }
+
+    #[test]
+    fn entry() {
+        let mut world = World::new();
+
+        assert_eq!(*world.entry::<u32>("a").or_insert(1), 1);
+        assert_eq!(*world.entry::<u32>("a").or_insert(2), 1);
+        assert_eq!(*world.entry::<u32>("b").or_insert_with(|| 3), 3);
+        assert_eq!(*world.entry::<u64>("a").or_default(), 0);
+
+        let entry = world.entry::<u32>("c").and_modify(|_| panic!("Vacant"));
+        assert_eq!(entry.key(), &"c");
+
+        world
+            .entry::<u32>("a")
+            .and_modify(|a| *a += 10)
+            .or_insert(0);
+        *world.get_or_insert_default::<u64>("b") += 5;
+
+        assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&11));
+        assert_eq!(world.get::<u32, _>("c").as_deref(), None);
+        assert_eq!(world.get::<u64, _>("b").as_deref(), Some(&5));
+        assert_eq!(world.len(), 4);
+
+        world.entry::<i8>("a");
+        assert!(!world.types().any(|(id, _)| id == TypeId::of::<i8>()));
+        assert!(World::<&str>::new().is_empty());
+    }
+
+    #[test]
+    fn dynamic() {
+        let mut world = World::new();
+        world.register::<u32>("int");
+        world.insert("a", 1u32);
+
+        assert_eq!(
+            world
+                .get_dyn("int", "a")
+                .and_then(|r| r.downcast_ref::<u32>().copied()),
+            Some(1)
+        );
+        assert!(world.get_dyn("int", "b").is_none());
+        assert!(world.get_dyn("float", "a").is_none());
+
+        let old = world.insert_dyn("a", Box::new(2u32)).unwrap();
+        assert_eq!(old.downcast_ref::<u32>(), Some(&1));
+        assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&2));
+
+        world.insert_dyn("b", Box::new(3u64));
+        *world
+            .get_dyn_mut(TypeId::of::<u64>(), "b")
+            .and_then(|r| r.downcast_mut::<u64>())
+            .unwrap() += 1;
+        assert_eq!(world.get::<u64, _>("b").as_deref(), Some(&4));
+        assert!(world.types().any(|(_, name)| name == "u64"));
+
+        let removed = world.remove_dyn("int", "a").unwrap();
+        assert_eq!(removed.downcast::<u32>().ok().map(|b| *b), Some(2));
+        assert!(!world.contains::<u32, _>("a"));
+        assert_eq!(world.len(), 1);
+    }
+
+    #[test]
+    fn child() {
+        let mut root = World::new();
+        root.register::<u32>("int");
+        root.insert("a", 1u32);
+        root.insert("b", 2u32);
+
+        let mut parent = World::child(Arc::new(root));
+        parent.insert("b", 3u32);
+
+        let mut child = World::child(Arc::new(parent));
+        child.insert("c", 4u32);
+
+        assert_eq!(child.get::<u32, _>("a").as_deref(), Some(&1));
+        assert_eq!(child.get::<u32, _>("b").as_deref(), Some(&3));
+        assert_eq!(*child.fetch::<u32, _>("c"), 4);
+        assert!(child.contains::<u32, _>("a"));
+        assert!(!child.contains::<u32, _>("d"));
+        assert_eq!(
+            child
+                .get_dyn("int", "a")
+                .and_then(|r| r.downcast_ref::<u32>().copied()),
+            Some(1)
+        );
+
+        assert_eq!(
+            child.try_fetch_mut::<u32, _>("a").unwrap_err(),
+            FetchError::Missing { type_name: "u32" }
+        );
+        assert_eq!(child.get_mut::<u32, _>("a"), None);
+        assert_eq!(child.len(), 1);
+
+        // Overriding a resource of the parent leaves the parent unchanged.
+        *child.entry::<u32>("a").or_insert(5) += 1;
+        assert_eq!(child.get::<u32, _>("a").as_deref(), Some(&6));
+        assert_eq!(
+            child.parent().unwrap().get::<u32, _>("a").as_deref(),
+            Some(&1)
+        );
+
+        let (a, b) = child.fetch_data::<(ReadKey<u32>, ReadKey<u32>)>(("a", "b"));
+        assert_eq!((*a, *b), (6, 3));
+    }
+
+    #[test]
+    fn changes() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.track_changes();
+        let start = world.change_tick();
+
+        world.insert("b", 2u32);
+        let inserted = world.change_tick();
+        assert!(!world.changed_since::<u32, _>("a", start));
+        assert!(world.changed_since::<u32, _>("b", start));
+        assert!(!world.changed_since::<u32, _>("b", inserted));
+
+        *world.fetch_mut::<u32, _>("a") += 1;
+        let fetched = world.change_tick();
+        assert!(world.changed_since::<u32, _>("a", inserted));
+        assert!(!world.changed_since::<u32, _>("a", fetched));
+
+        // Shared access does not count as a change.
+        world.fetch::<u32, _>("a");
+        world.get::<u32, _>("a");
+        assert!(!world.changed_since::<u32, _>("a", fetched));
+
+        world.remove::<u32, _>("b");
+        assert!(world.changed_since::<u32, _>("b", fetched));
+        assert!(!world.changed_since::<u32, _>("c", start));
+
+        // An unused entry neither forgets the removal nor counts as a change.
+        let removed = world.change_tick();
+        world.entry::<u32>("b");
+        world.entry::<u32>("a");
+        world.entry::<u32>("b").and_modify(|_| {});
+        assert!(world.changed_since::<u32, _>("b", fetched));
+        assert!(!world.changed_since::<u32, _>("a", removed));
+        world.entry::<u32>("a").and_modify(|a| *a += 1);
+        assert!(world.changed_since::<u32, _>("a", removed));
+
+        world.entry::<u64>("c").or_default();
+
+        let mut changes: Vec<_> = world
+            .changes_since(inserted)
+            .map(|c| (*c.key, c.type_name, c.removed))
+            .collect();
+        changes.sort();
+        assert_eq!(
+            changes,
+            [("a", "u32", false), ("b", "u32", true), ("c", "u64", false)]
+        );
+
+        world.clear_removed(world.change_tick());
+        assert!(!world.changed_since::<u32, _>("b", fetched));
+        world.insert("b", 3u32);
+        let mut drained: Vec<_> = world.drain::<u32>().collect();
+        drained.sort();
+        assert_eq!(drained, [("a", 3), ("b", 3)]);
+        assert_eq!(
+            world.changes_since(inserted).filter(|c| c.removed).count(),
+            2
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Change tracking is not enabled")]
+    fn changes_disabled() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.changed_since::<u32, _>("a", 0);
+    }
+
+    #[test]
+    fn fetch() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.insert("b", 2u32);
+
+        {
+            let a1 = world.fetch::<u32, _>("a");
+            let a2 = world.fetch::<u32, _>("a");
+            let mut b = world.fetch_mut::<u32, _>("b");
+
+            *b += *a1 + *a2;
+        }
+
+        assert_eq!(*world.fetch::<u32, _>("b"), 4);
+        assert_eq!(
+            world.try_fetch::<u64, _>("a").unwrap_err(),
+            FetchError::Missing { type_name: "u64" }
+        );
+    }
+
+    #[test]
+    fn fetch_conflicts() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+
+        {
+            let _a = world.fetch::<u32, _>("a");
+
+            assert_eq!(
+                world.try_fetch_mut::<u32, _>("a").unwrap_err(),
+                FetchError::Borrowed { type_name: "u32" }
+            );
+        }
+
+        {
+            let _a = world.fetch_mut::<u32, _>("a");
+
+            assert_eq!(
+                world.try_fetch::<u32, _>("a").unwrap_err(),
+                FetchError::BorrowedMut { type_name: "u32" }
+            );
+            assert_eq!(
+                world.try_fetch_mut::<u32, _>("a").unwrap_err(),
+                FetchError::BorrowedMut { type_name: "u32" }
+            );
+        }
+
+        world.fetch_mut::<u32, _>("a");
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed mutably")]
+    fn fetch_mut_twice() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+
+        let _a = world.fetch_mut::<u32, _>("a");
+        let _b = world.fetch_mut::<u32, _>("a");
+    }
+
+    #[test]
+    fn get_then_fetch_mut() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+
+        let a = world.get::<u32, _>("a").unwrap();
+        assert!(world.try_fetch_mut::<u32, _>("a").is_err());
+        assert_eq!(*world.fetch::<u32, _>("a"), 1);
+        drop(a);
+
+        *world.fetch_mut::<u32, _>("a") = 2;
+        assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&2));
+    }
+
+    #[test]
+    #[should_panic(expected = "already borrowed mutably")]
+    fn get_while_fetched_mut() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+
+        let _a = world.fetch_mut::<u32, _>("a");
+        world.get::<u32, _>("a");
+    }
+
+    #[test]
+    fn fetch_many() {
+        let mut world = World::new();
+        world.insert("a", 1u32);
+        world.insert("b", 2u32);
+        world.insert("a", 3u64);
+
+        {
+            let (a, b, mut c) = world.fetch_many::<(&u32, &u32, &mut u64)>((&"a", &"b", &"a"));
+            *c += u64::from(*a + *b);
+        }
+
+        assert_eq!(world.get::<u64, _>("a").as_deref(), Some(&6));
+        assert_eq!(
+            world
+                .try_fetch_many::<(&mut u32, &u32)>((&"a", &"a"))
+                .unwrap_err(),
+            FetchError::BorrowedMut { type_name: "u32" }
+        );
+        assert!(world.try_fetch::<u32, _>("a").is_ok());
+    }
+
+    #[test]
+    fn fetch_data() {
+        let mut world = World::<&str>::new();
+        world.insert("", 1u32);
+        world.insert("", 2u64);
+        world.insert("x", 3u32);
+
+        {
+            let (a, x, mut b, missing) = world.fetch_data::<(
+                Read<u32>,
+                ReadKey<u32>,
+                Write<u64>,
+                Option<Read<i8>>,
+            )>(((), "x", (), ()));
+            *b += u64::from(*a + *x);
+            assert!(missing.is_none());
+        }
+
+        assert_eq!(world.get::<u64, _>("").as_deref(), Some(&6));
+        assert_eq!(
+            world
+                .try_fetch_data::<(Read<u32>, Read<i8>)>(((), ()))
+                .unwrap_err(),
+            FetchError::Missing { type_name: "i8" }
+        );
+        assert_eq!(
+            world
+                .try_fetch_data::<(Option<Write<u32>>, Read<u32>)>(((), ()))
+                .unwrap_err(),
+            FetchError::BorrowedMut { type_name: "u32" }
+        );
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derive_fetch() {
+        #[derive(Fetch)]
+        struct Data<'a> {
+            a: Read<'a, u32>,
+            x: ReadKey<'a, u32>,
+            b: Write<'a, u64>,
+        }
+
+        #[derive(Fetch)]
+        struct Nested<'a>(Data<'a>, Option<Read<'a, i8>>);
+
+        let mut world = World::<&str>::new();
+        world.insert("", 1u32);
+        world.insert("", 2u64);
+        world.insert("x", 3u32);
+
+        {
+            let Nested(mut data, missing) = world.fetch_data::<Nested>((((), "x", ()), ()));
+            *data.b += u64::from(*data.a + *data.x);
+            assert!(missing.is_none());
+        }
+
+        assert_eq!(world.get::<u64, _>("").as_deref(), Some(&6));
+    }
+
+    #[test]
+    fn threads() {
+        let mut world = World::new();
+        world.insert(0, 0u32);
+        world.insert(1, 0u32);
+
+        std::thread::scope(|s| {
+            for i in 0..2 {
+                let world = &world;
+
+                s.spawn(move || {
+                    for _ in 0..100 {
+                        *world.fetch_mut::<u32, _>(&i) += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(world.get::<u32, _>(&0).as_deref(), Some(&100));
+        assert_eq!(world.get::<u32, _>(&1).as_deref(), Some(&100));
+    }
 }
@@ -0,0 +1,377 @@
+//! A registry of resource types, allowing dynamically typed access to a
+//! `World`.
+
+use std::{
+    any::{type_name, TypeId},
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+};
+
+use hashbrown::HashMap;
+
+use crate::Resource;
+
+/// Identifies a resource type at runtime, either by its `TypeId` or by the
+/// name it was registered under.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TypeKey<'a> {
+    /// The `TypeId` of the resource.
+    Id(TypeId),
+    /// The name the type was registered under in the `TypeRegistry`.
+    Name(&'a str),
+}
+
+impl From<TypeId> for TypeKey<'_> {
+    fn from(id: TypeId) -> Self {
+        TypeKey::Id(id)
+    }
+}
+
+impl<'a> From<&'a str> for TypeKey<'a> {
+    fn from(name: &'a str) -> Self {
+        TypeKey::Name(name)
+    }
+}
+
+type CloneFn = fn(&dyn Resource) -> Box<dyn Resource>;
+type DebugFn = fn(&dyn Resource, &mut Formatter<'_>) -> fmt::Result;
+#[cfg(feature = "serde")]
+type SerializeFn = fn(&dyn Resource) -> &dyn erased_serde::Serialize;
+#[cfg(feature = "serde")]
+type DeserializeFn =
+    fn(&mut dyn erased_serde::Deserializer<'_>) -> Result<Box<dyn Resource>, erased_serde::Error>;
+
+/// What to do with resources whose type is not registered with the support
+/// an operation requires, e.g. `Register::with_clone` for `World::snapshot`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Unregistered {
+    /// Leave them out, or ignore them when deserializing.
+    Skip,
+    /// Fail with an error.
+    Error,
+}
+
+/// Information about a registered resource type, together with the
+/// operations that can be performed on it without knowing its static type.
+pub struct Registration {
+    name: String,
+    type_id: TypeId,
+    type_name: &'static str,
+    clone: Option<CloneFn>,
+    debug: Option<DebugFn>,
+    #[cfg(feature = "serde")]
+    serde: Option<(SerializeFn, DeserializeFn)>,
+}
+
+impl Registration {
+    fn new<T: Resource>(name: String) -> Self {
+        Registration {
+            name,
+            type_id: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            clone: None,
+            debug: None,
+            #[cfg(feature = "serde")]
+            serde: None,
+        }
+    }
+
+    /// Returns the name the type was registered under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the `TypeId` of the type.
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Returns the Rust name of the type, as given by `std::any::type_name`.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Clones `value`, returning `None` if the type was not registered as
+    /// `Clone`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is not of the registered type.
+    pub fn clone_resource(&self, value: &dyn Resource) -> Option<Box<dyn Resource>> {
+        self.check(value);
+
+        self.clone.map(|clone| clone(value))
+    }
+
+    /// Returns a wrapper implementing `Debug` for `value`, or `None` if the
+    /// type was not registered as `Debug`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is not of the registered type.
+    pub fn debug<'a>(&self, value: &'a dyn Resource) -> Option<impl Debug + 'a> {
+        self.check(value);
+
+        self.debug.map(|fmt| DynDebug { value, fmt })
+    }
+
+    /// Returns `value` as a serializable trait object, or `None` if the type
+    /// was not registered with serde support.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is not of the registered type.
+    #[cfg(feature = "serde")]
+    pub fn serialize<'a>(
+        &self,
+        value: &'a dyn Resource,
+    ) -> Option<&'a dyn erased_serde::Serialize> {
+        self.check(value);
+
+        self.serde.map(|(serialize, _)| serialize(value))
+    }
+
+    /// Deserializes a resource of the registered type, or returns `None` if
+    /// the type was not registered with serde support.
+    #[cfg(feature = "serde")]
+    pub fn deserialize(
+        &self,
+        deserializer: &mut dyn erased_serde::Deserializer<'_>,
+    ) -> Option<Result<Box<dyn Resource>, erased_serde::Error>> {
+        self.serde.map(|(_, deserialize)| deserialize(deserializer))
+    }
+
+    pub(crate) fn supports_clone(&self) -> bool {
+        self.clone.is_some()
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn supports_serde(&self) -> bool {
+        self.serde.is_some()
+    }
+
+    fn check(&self, value: &dyn Resource) {
+        assert!(
+            (*value).get_type_id() == self.type_id,
+            "Expected a resource of type `{}`",
+            self.type_name
+        );
+    }
+}
+
+impl Debug for Registration {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("Registration");
+        f.field("name", &self.name)
+            .field("type_name", &self.type_name)
+            .field("clone", &self.clone.is_some())
+            .field("debug", &self.debug.is_some());
+        #[cfg(feature = "serde")]
+        f.field("serde", &self.serde.is_some());
+
+        f.finish()
+    }
+}
+
+struct DynDebug<'a> {
+    value: &'a dyn Resource,
+    fmt: DebugFn,
+}
+
+impl Debug for DynDebug<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        (self.fmt)(self.value, f)
+    }
+}
+
+/// Maps resource types to `Registration`s, which can be looked up by
+/// `TypeId` or by name.
+///
+/// ```
+/// use std::any::TypeId;
+///
+/// use nitric_world::TypeRegistry;
+///
+/// #[derive(Clone, Debug)]
+/// struct Gravity(f32);
+///
+/// let mut registry = TypeRegistry::new();
+/// registry.register::<Gravity>("gravity").with_clone().with_debug();
+///
+/// let registration = registry.get("gravity").unwrap();
+/// assert_eq!(registration.type_id(), TypeId::of::<Gravity>());
+///
+/// let copy = registration.clone_resource(&Gravity(9.81)).unwrap();
+/// let debug = format!("{:?}", registration.debug(&*copy).unwrap());
+/// assert_eq!(debug, "Gravity(9.81)");
+/// ```
+#[derive(Debug, Default)]
+pub struct TypeRegistry {
+    types: HashMap<TypeId, Registration>,
+    names: HashMap<String, TypeId>,
+}
+
+impl TypeRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers `T` under `name`, replacing any previous registration of
+    /// `T`. The returned handle can be used to register the operations `T`
+    /// supports.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is already used by another type.
+    pub fn register<T: Resource>(&mut self, name: impl Into<String>) -> Register<'_, T> {
+        let name = name.into();
+        let id = TypeId::of::<T>();
+
+        match self.names.get(&name) {
+            Some(&other) if other != id => panic!(
+                "Cannot register `{}` as \"{}\", the name is used by `{}`",
+                type_name::<T>(),
+                name,
+                self.types[&other].type_name
+            ),
+            _ => {}
+        }
+
+        if let Some(old) = self.types.remove(&id) {
+            self.names.remove(&old.name);
+        }
+
+        self.names.insert(name.clone(), id);
+        let registration = self
+            .types
+            .entry(id)
+            .or_insert_with(|| Registration::new::<T>(name));
+
+        Register {
+            registration,
+            marker: PhantomData,
+        }
+    }
+
+    /// Looks up the registration of a type.
+    pub fn get<'a>(&self, ty: impl Into<TypeKey<'a>>) -> Option<&Registration> {
+        self.resolve(ty).and_then(|id| self.types.get(&id))
+    }
+
+    /// Returns the `TypeId` of a type, which requires it to be registered if
+    /// it is given by name.
+    pub fn resolve<'a>(&self, ty: impl Into<TypeKey<'a>>) -> Option<TypeId> {
+        match ty.into() {
+            TypeKey::Id(id) => Some(id),
+            TypeKey::Name(name) => self.names.get(name).cloned(),
+        }
+    }
+
+    /// Iterates over all registrations.
+    pub fn iter(&self) -> impl Iterator<Item = &Registration> {
+        self.types.values()
+    }
+}
+
+/// A handle for adding operations to the registration of `T`, returned by
+/// `TypeRegistry::register`.
+pub struct Register<'a, T> {
+    registration: &'a mut Registration,
+    marker: PhantomData<T>,
+}
+
+impl<'a, T: Resource> Register<'a, T> {
+    /// Allows cloning resources of type `T` through the registry.
+    pub fn with_clone(self) -> Self
+    where
+        T: Clone,
+    {
+        self.registration.clone = Some(|value| Box::new(downcast::<T>(value).clone()));
+
+        self
+    }
+
+    /// Allows debug-formatting resources of type `T` through the registry.
+    pub fn with_debug(self) -> Self
+    where
+        T: Debug,
+    {
+        self.registration.debug = Some(|value, f| Debug::fmt(downcast::<T>(value), f));
+
+        self
+    }
+
+    /// Allows serializing and deserializing resources of type `T` through
+    /// the registry, which is required for them to be saved with the
+    /// `World`.
+    #[cfg(feature = "serde")]
+    pub fn with_serde(self) -> Self
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        self.registration.serde = Some((
+            |value| downcast::<T>(value),
+            |deserializer| Ok(Box::new(erased_serde::deserialize::<T>(deserializer)?)),
+        ));
+
+        self
+    }
+}
+
+impl<T> Debug for Register<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Register").field(self.registration).finish()
+    }
+}
+
+fn downcast<T: Resource>(value: &dyn Resource) -> &T {
+    value.downcast_ref().expect("Unreachable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<u32>("a").with_clone();
+        registry.register::<u64>("b").with_debug();
+
+        let a = registry.get("a").unwrap();
+        assert_eq!(a.type_name(), "u32");
+        assert_eq!(
+            a.clone_resource(&5u32).unwrap().downcast_ref::<u32>(),
+            Some(&5)
+        );
+        assert!(a.debug(&5u32).is_none());
+
+        let b = registry.get(TypeId::of::<u64>()).unwrap();
+        assert_eq!(b.name(), "b");
+        assert!(b.clone_resource(&5u64).is_none());
+        assert_eq!(format!("{:?}", b.debug(&5u64).unwrap()), "5");
+
+        // Registering again replaces the previous registration.
+        registry.register::<u32>("c");
+        assert!(registry.get("a").is_none());
+        assert!(registry.get("c").unwrap().clone_resource(&5u32).is_none());
+        assert_eq!(registry.iter().count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "the name is used by `u32`")]
+    fn name_taken() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<u32>("a");
+        registry.register::<u64>("a");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a resource of type `u32`")]
+    fn wrong_type() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<u32>("a").with_clone();
+        registry.get("a").unwrap().clone_resource(&5u64);
+    }
+}
@@ -0,0 +1,375 @@
+//! Serialization of a `World` through its `TypeRegistry`.
+//!
+//! A world is serialized as a map from registered type names to maps from
+//! keys to values. The type names are sorted, but the keys of each type are
+//! written in an unspecified order.
+
+use std::{
+    fmt::{self, Formatter},
+    hash::Hash,
+    marker::PhantomData,
+};
+
+use hashbrown::HashMap;
+use serde::{
+    de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor},
+    ser::{self, SerializeMap, Serializer},
+    Deserialize, Serialize,
+};
+
+use crate::{cell::ResourceCell, Registration, Resource, Unregistered, World};
+
+impl<K: Hash + Eq> World<K> {
+    /// Returns a wrapper which serializes the world, treating resources of
+    /// unregistered types as specified by `unregistered`.
+    ///
+    /// Serializing the `World` directly is equivalent to
+    /// `serializable(Unregistered::Error)`.
+    ///
+    /// ```
+    /// use nitric_world::{Unregistered, World};
+    ///
+    /// let mut world = World::new();
+    /// world.register::<u32>("score").with_serde();
+    /// world.insert("player", 42u32);
+    /// world.insert("player", "not registered");
+    ///
+    /// let json = serde_json::to_string(&world.serializable(Unregistered::Skip)).unwrap();
+    /// assert_eq!(json, r#"{"score":{"player":42}}"#);
+    ///
+    /// let mut loaded = World::<String>::new();
+    /// loaded.register::<u32>("score").with_serde();
+    /// loaded
+    ///     .deserialize_into(
+    ///         &mut serde_json::Deserializer::from_str(&json),
+    ///         Unregistered::Error,
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(loaded.get::<u32, _>("player").as_deref(), Some(&42));
+    /// ```
+    pub fn serializable(&self, unregistered: Unregistered) -> SerializeWorld<'_, K> {
+        SerializeWorld {
+            world: self,
+            unregistered,
+        }
+    }
+
+    /// Deserializes resources into this world, which are looked up by their
+    /// registered names, replacing existing resources with the same type and
+    /// key. Types which are not registered with serde support are treated as
+    /// specified by `unregistered`.
+    ///
+    /// If deserialization fails, the world is left unchanged.
+    pub fn deserialize_into<'de, D>(
+        &mut self,
+        deserializer: D,
+        unregistered: Unregistered,
+    ) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+        K: Deserialize<'de>,
+    {
+        deserializer.deserialize_map(WorldVisitor {
+            world: self,
+            unregistered,
+        })
+    }
+}
+
+impl<K> Serialize for World<K>
+where
+    K: Hash + Eq + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serializable(Unregistered::Error).serialize(serializer)
+    }
+}
+
+/// Serializes a `World`, returned by `World::serializable`.
+pub struct SerializeWorld<'a, K>
+where
+    K: Hash + Eq,
+{
+    world: &'a World<K>,
+    unregistered: Unregistered,
+}
+
+impl<K> Serialize for SerializeWorld<'_, K>
+where
+    K: Hash + Eq + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let world = self.world;
+        let mut types = Vec::with_capacity(world.resources.len());
+
+        for (id, resources) in &world.resources {
+            match world.registry.get(*id) {
+                Some(registration) if registration.supports_serde() => {
+                    types.push((registration, resources))
+                }
+                _ if resources.is_empty() || self.unregistered == Unregistered::Skip => {}
+                _ => {
+                    return Err(ser::Error::custom(format_args!(
+                        "Resource type `{}` is not registered for serialization",
+                        world.type_names[id]
+                    )))
+                }
+            }
+        }
+
+        // Sort the types, so their order doesn't depend on the hash map. The
+        // keys of each type are still written in the order of the hash map.
+        types.sort_by(|a, b| a.0.name().cmp(b.0.name()));
+
+        let mut map = serializer.serialize_map(Some(types.len()))?;
+        for (registration, resources) in types {
+            map.serialize_entry(
+                registration.name(),
+                &SerializeResources {
+                    registration,
+                    resources,
+                },
+            )?;
+        }
+
+        map.end()
+    }
+}
+
+struct SerializeResources<'a, K> {
+    registration: &'a Registration,
+    resources: &'a HashMap<K, ResourceCell>,
+}
+
+impl<K> Serialize for SerializeResources<'_, K>
+where
+    K: Hash + Eq + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.resources.len()))?;
+
+        for (k, cell) in self.resources {
+            let value = cell
+                .borrow(self.registration.type_name())
+                .map_err(ser::Error::custom)?;
+            let value = self.registration.serialize(&*value).expect("Unreachable");
+
+            map.serialize_entry(k, value)?;
+        }
+
+        map.end()
+    }
+}
+
+struct WorldVisitor<'a, K>
+where
+    K: Hash + Eq,
+{
+    world: &'a mut World<K>,
+    unregistered: Unregistered,
+}
+
+impl<'de, K> Visitor<'de> for WorldVisitor<'_, K>
+where
+    K: Hash + Eq + Deserialize<'de>,
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("a map from resource type names to resources")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        // Only insert once the whole map has been read, so an error leaves the
+        // world untouched.
+        let mut loaded = Vec::new();
+
+        while let Some(name) = map.next_key::<String>()? {
+            let id = self
+                .world
+                .registry
+                .get(name.as_str())
+                .filter(|registration| registration.supports_serde())
+                .map(Registration::type_id);
+
+            let id = match (id, self.unregistered) {
+                (Some(id), _) => id,
+                (None, Unregistered::Skip) => {
+                    map.next_value::<IgnoredAny>()?;
+
+                    continue;
+                }
+                (None, Unregistered::Error) => {
+                    return Err(de::Error::custom(format_args!(
+                        "Resource type \"{}\" is not registered for deserialization",
+                        name
+                    )))
+                }
+            };
+
+            loaded.extend(map.next_value_seed(ResourcesSeed {
+                registration: self.world.registry.get(id).expect("Unreachable"),
+                marker: PhantomData,
+            })?);
+        }
+
+        for (k, v) in loaded {
+            self.world.insert_dyn(k, v);
+        }
+
+        Ok(())
+    }
+}
+
+struct ResourcesSeed<'a, K> {
+    registration: &'a Registration,
+    marker: PhantomData<K>,
+}
+
+impl<'de, K> DeserializeSeed<'de> for ResourcesSeed<'_, K>
+where
+    K: Deserialize<'de>,
+{
+    type Value = Vec<(K, Box<dyn Resource>)>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, K> Visitor<'de> for ResourcesSeed<'_, K>
+where
+    K: Deserialize<'de>,
+{
+    type Value = Vec<(K, Box<dyn Resource>)>;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a map from keys to resources of type `{}`",
+            self.registration.name()
+        )
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut resources = Vec::with_capacity(map.size_hint().unwrap_or(0));
+
+        while let Some(k) = map.next_key()? {
+            let v = map.next_value_seed(ResourceSeed(self.registration))?;
+
+            resources.push((k, v));
+        }
+
+        Ok(resources)
+    }
+}
+
+struct ResourceSeed<'a>(&'a Registration);
+
+impl<'de> DeserializeSeed<'de> for ResourceSeed<'_> {
+    type Value = Box<dyn Resource>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+
+        self.0
+            .deserialize(&mut deserializer)
+            .expect("Unreachable")
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registered_world() -> World<String> {
+        let mut world = World::new();
+        world.register::<u32>("int").with_serde();
+        world.register::<Vec<String>>("names").with_serde();
+
+        world
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut world = registered_world();
+        world.insert("a".to_owned(), 1u32);
+        world.insert("b".to_owned(), 2u32);
+        world.insert("a".to_owned(), vec!["x".to_owned()]);
+
+        let json = serde_json::to_value(&world).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "int": { "a": 1, "b": 2 }, "names": { "a": ["x"] } })
+        );
+
+        let mut loaded = registered_world();
+        loaded.insert("a".to_owned(), 7u32);
+        loaded.insert("c".to_owned(), 8u32);
+        loaded.deserialize_into(json, Unregistered::Error).unwrap();
+
+        assert_eq!(loaded.get::<u32, _>("a").as_deref(), Some(&1));
+        assert_eq!(loaded.get::<u32, _>("b").as_deref(), Some(&2));
+        assert_eq!(loaded.get::<u32, _>("c").as_deref(), Some(&8));
+        assert_eq!(
+            loaded.get::<Vec<String>, _>("a").as_deref(),
+            Some(&vec!["x".to_owned()])
+        );
+    }
+
+    #[test]
+    fn unregistered() {
+        let mut world = registered_world();
+        world.insert("a".to_owned(), 1u32);
+        world.insert("a".to_owned(), 1u64);
+
+        assert!(serde_json::to_value(&world)
+            .unwrap_err()
+            .to_string()
+            .contains("`u64` is not registered"));
+        let json = serde_json::to_value(world.serializable(Unregistered::Skip)).unwrap();
+        assert_eq!(json, serde_json::json!({ "int": { "a": 1 } }));
+
+        let json = serde_json::json!({ "int": { "a": 2 }, "float": { "a": 1.0 } });
+        let mut loaded = registered_world();
+        assert!(loaded
+            .deserialize_into(&json, Unregistered::Error)
+            .unwrap_err()
+            .to_string()
+            .contains("\"float\" is not registered"));
+
+        let mut loaded = registered_world();
+        loaded.deserialize_into(&json, Unregistered::Skip).unwrap();
+        assert_eq!(loaded.get::<u32, _>("a").as_deref(), Some(&2));
+        assert_eq!(loaded.len(), 1);
+    }
+
+    #[test]
+    fn invalid_leaves_world_unchanged() {
+        let mut world = registered_world();
+        world.insert("a".to_owned(), 1u32);
+        world.insert("a".to_owned(), vec!["x".to_owned()]);
+
+        let json = serde_json::json!({ "int": { "a": 2, "b": 3 }, "names": { "a": 4 } });
+        assert!(world.deserialize_into(json, Unregistered::Error).is_err());
+
+        assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&1));
+        assert!(!world.contains::<u32, _>("b"));
+        assert_eq!(
+            world.get::<Vec<String>, _>("a").as_deref(),
+            Some(&vec!["x".to_owned()])
+        );
+        assert_eq!(world.len(), 2);
+    }
+
+    #[test]
+    fn borrowed_mutably() {
+        let mut world = registered_world();
+        world.insert("a".to_owned(), 1u32);
+
+        let _a = world.fetch_mut::<u32, _>("a");
+        assert!(serde_json::to_value(&world).is_err());
+    }
+}
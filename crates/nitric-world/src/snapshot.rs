@@ -0,0 +1,221 @@
+//! Snapshots of the cloneable resources of a `World`.
+
+use std::{
+    any::TypeId,
+    fmt::{self, Debug, Formatter},
+    hash::Hash,
+};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{Resource, SnapshotError, Unregistered, World};
+
+/// A deep copy of the resources of a `World`, created with `World::snapshot`
+/// and applied with `World::restore`.
+pub struct Snapshot<K> {
+    /// The types covered by the snapshot, which includes types registered as
+    /// `Clone` that had no resources at the time.
+    types: HashSet<TypeId>,
+    resources: HashMap<TypeId, HashMap<K, Box<dyn Resource>>>,
+    skipped: Vec<&'static str>,
+}
+
+impl<K> Snapshot<K>
+where
+    K: Hash + Eq,
+{
+    /// Returns the number of resources in the snapshot.
+    pub fn len(&self) -> usize {
+        self.resources.values().map(HashMap::len).sum()
+    }
+
+    /// Checks if the snapshot contains no resources.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the names of the types which were skipped because they are
+    /// not registered as `Clone`.
+    pub fn skipped(&self) -> &[&'static str] {
+        &self.skipped
+    }
+}
+
+impl<K> Debug for Snapshot<K>
+where
+    K: Hash + Eq,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("len", &self.len())
+            .field("skipped", &self.skipped)
+            .finish()
+    }
+}
+
+impl<K> World<K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates a deep copy of all resources whose type is registered as
+    /// `Clone` (see `Register::with_clone`). Other resources are skipped or
+    /// cause an error, depending on `unregistered`.
+    ///
+    /// ```
+    /// use nitric_world::{Unregistered, World};
+    ///
+    /// let mut world = World::new();
+    /// world.register::<String>("name").with_clone();
+    /// world.insert("player", "Alice".to_owned());
+    ///
+    /// let snapshot = world.snapshot(Unregistered::Error).unwrap();
+    /// world.insert("player", "Bob".to_owned());
+    /// world.insert("enemy", "Eve".to_owned());
+    ///
+    /// let redo = world.restore(snapshot);
+    /// assert_eq!(world.get::<String, _>("player").as_deref().unwrap(), "Alice");
+    /// assert!(world.get::<String, _>("enemy").as_deref().is_none());
+    ///
+    /// world.restore(redo);
+    /// assert_eq!(world.get::<String, _>("player").as_deref().unwrap(), "Bob");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Fails if a resource is fetched mutably, or if `unregistered` is
+    /// `Unregistered::Error` and a resource type is not registered as
+    /// `Clone`.
+    pub fn snapshot(&self, unregistered: Unregistered) -> Result<Snapshot<K>, SnapshotError> {
+        let mut snapshot = Snapshot {
+            types: self
+                .registry
+                .iter()
+                .filter(|registration| registration.supports_clone())
+                .map(|registration| registration.type_id())
+                .collect(),
+            resources: HashMap::new(),
+            skipped: Vec::new(),
+        };
+
+        for (&id, resources) in self.resources.iter().filter(|(_, m)| !m.is_empty()) {
+            let type_name = self.type_names[&id];
+
+            let registration = match self.registry.get(id) {
+                Some(registration) if registration.supports_clone() => registration,
+                _ if unregistered == Unregistered::Skip => {
+                    snapshot.skipped.push(type_name);
+
+                    continue;
+                }
+                _ => return Err(SnapshotError::NotCloneable { type_name }),
+            };
+
+            let resources = resources
+                .iter()
+                .map(|(k, cell)| {
+                    let value = cell.borrow(type_name)?;
+                    let value = registration.clone_resource(&*value).expect("Unreachable");
+
+                    Ok((k.clone(), value))
+                })
+                .collect::<Result<_, SnapshotError>>()?;
+
+            snapshot.resources.insert(id, resources);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Replaces all resources of the types covered by `snapshot` with the
+    /// ones it contains. Resources of other types are left untouched.
+    ///
+    /// Returns a snapshot with the replaced resources, which can be restored
+    /// to undo this operation.
+    pub fn restore(&mut self, snapshot: Snapshot<K>) -> Snapshot<K> {
+        let mut replaced = Snapshot {
+            types: snapshot.types,
+            resources: HashMap::new(),
+            skipped: snapshot.skipped,
+        };
+
+        for &id in &replaced.types {
+            if let Some(resources) = self.resources.remove(&id) {
+                let type_name = self.type_names[&id];
+                let resources = resources
+                    .into_iter()
+                    .map(|(k, cell)| {
+                        self.changes.removed(id, type_name, k.clone());
+
+                        (k, cell.into_inner())
+                    })
+                    .collect();
+
+                replaced.resources.insert(id, resources);
+            }
+
+            self.type_names.remove(&id);
+        }
+
+        for (_, resources) in snapshot.resources {
+            for (k, v) in resources {
+                self.insert_dyn(k, v);
+            }
+        }
+
+        replaced
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FetchError;
+
+    #[test]
+    fn snapshot_restore() {
+        let mut world = World::new();
+        world.register::<u32>("int").with_clone();
+        world.register::<u64>("long").with_clone();
+        world.insert("a", 1u32);
+        world.insert("a", "not cloneable");
+
+        assert_eq!(
+            world.snapshot(Unregistered::Error).unwrap_err(),
+            SnapshotError::NotCloneable { type_name: "&str" }
+        );
+
+        let snapshot = world.snapshot(Unregistered::Skip).unwrap();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.skipped(), ["&str"]);
+
+        *world.get_mut::<u32, _>("a").unwrap() = 2;
+        world.insert("b", 3u32);
+        world.insert("a", 4u64);
+        world.insert("a", "changed");
+
+        let redo = world.restore(snapshot);
+        assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&1));
+        assert_eq!(world.get::<u32, _>("b").as_deref(), None);
+        assert_eq!(world.get::<u64, _>("a").as_deref(), None);
+        assert_eq!(world.get::<&str, _>("a").as_deref(), Some(&"changed"));
+        assert_eq!(redo.len(), 3);
+
+        world.restore(redo);
+        assert_eq!(world.get::<u32, _>("a").as_deref(), Some(&2));
+        assert_eq!(world.get::<u32, _>("b").as_deref(), Some(&3));
+        assert_eq!(world.get::<u64, _>("a").as_deref(), Some(&4));
+    }
+
+    #[test]
+    fn borrowed_mutably() {
+        let mut world = World::new();
+        world.register::<u32>("int").with_clone();
+        world.insert("a", 1u32);
+
+        let _a = world.fetch_mut::<u32, _>("a");
+        assert_eq!(
+            world.snapshot(Unregistered::Error).unwrap_err(),
+            SnapshotError::Fetch(FetchError::BorrowedMut { type_name: "u32" })
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! A thread-safe world, storing every resource in a `nitric_lock::RwLock`.
+
+use std::{
+    any::{type_name, TypeId},
+    borrow::Borrow,
+    hash::Hash,
+};
+
+use hashbrown::HashMap;
+use nitric_lock::{
+    LockGroup, LockResult, LockSet, LockToken, PoisonError, ReadLock, RwLock, RwLockReadGuard,
+    RwLockWriteGuard, WriteLock,
+};
+
+use crate::{FetchError, LockError, Resource};
+
+/// Like `World`, but every resource lives in its own `RwLock`, allocated from
+/// an internal `LockGroup`.
+///
+/// Threads can lock disjoint resources concurrently. Since all locks belong to
+/// the same group, acquiring several of them at once with a `LockSet` (or
+/// `nitric_lock::lock!`) always happens in a deadlock-free order:
+///
+/// ```
+/// use nitric_lock::{lock, ReadLock, WriteLock};
+/// use nitric_world::SyncWorld;
+///
+/// let mut world = SyncWorld::new();
+/// world.insert("dt", 0.5f32);
+/// world.insert("pos", 1.0f32);
+///
+/// let mut token = world.token();
+/// let (mut pos, dt) = lock!(
+///     &mut token,
+///     world.get_lock::<f32, _>("pos").unwrap().write(),
+///     world.get_lock::<f32, _>("dt").unwrap().read(),
+/// )
+/// .unwrap();
+///
+/// *pos += *dt;
+/// ```
+pub struct SyncWorld<K>
+where
+    K: Hash + Eq,
+{
+    group: LockGroup,
+    resources: HashMap<TypeId, HashMap<K, Box<dyn Resource>>>,
+}
+
+impl<K: Hash + Eq> Default for SyncWorld<K> {
+    fn default() -> Self {
+        SyncWorld::new()
+    }
+}
+
+impl<K: Hash + Eq> SyncWorld<K> {
+    /// Creates an empty world with its own `LockGroup`.
+    pub fn new() -> Self {
+        SyncWorld {
+            group: LockGroup::new(),
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Returns a token for locking resources of this world on the current
+    /// thread. See `LockGroup::token`.
+    pub fn token(&self) -> LockToken {
+        self.group.token()
+    }
+
+    /// Adds a resource to the world, placing it in a new lock.
+    pub fn insert<T: Resource>(&mut self, k: K, v: T) -> Option<T> {
+        let lock = self.group.rw_lock(v);
+
+        self.resources
+            .entry(TypeId::of::<T>())
+            .or_default()
+            .insert(k, Box::new(lock))
+            .map(|b| Self::downcast::<T>(b).into_inner())
+    }
+
+    /// Returns the lock of a resource, which can be acquired together with
+    /// other locks of this world.
+    pub fn get_lock<T: Resource, Q>(&self, k: &Q) -> Option<&RwLock<T>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.resources
+            .get(&TypeId::of::<T>())
+            .and_then(|m| m.get(k))
+            .and_then(|b| b.downcast_ref())
+    }
+
+    /// Like `get_lock`, but fails with a `FetchError` if the resource is
+    /// missing.
+    pub fn try_get_lock<T: Resource, Q>(&self, k: &Q) -> Result<&RwLock<T>, FetchError>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.get_lock(k).ok_or(FetchError::Missing {
+            type_name: type_name::<T>(),
+        })
+    }
+
+    /// Locks a single resource for reading.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `LockError::Fetch` if the resource does not exist, or with
+    /// `LockError::Poisoned` carrying the guard if the lock is poisoned.
+    pub fn read<'a, T: Resource, Q>(
+        &'a self,
+        token: &'a mut LockToken,
+        k: &Q,
+    ) -> Result<RwLockReadGuard<'a, T>, LockError<RwLockReadGuard<'a, T>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let lock = self.try_get_lock::<T, Q>(k)?;
+
+        Ok(first((lock.read(),).lock(token))?)
+    }
+
+    /// Locks a single resource for writing.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `LockError::Fetch` if the resource does not exist, or with
+    /// `LockError::Poisoned` carrying the guard if the lock is poisoned.
+    pub fn write<'a, T: Resource, Q>(
+        &'a self,
+        token: &'a mut LockToken,
+        k: &Q,
+    ) -> Result<RwLockWriteGuard<'a, T>, LockError<RwLockWriteGuard<'a, T>>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let lock = self.try_get_lock::<T, Q>(k)?;
+
+        Ok(first((lock.write(),).lock(token))?)
+    }
+
+    /// Retrieves a mutable reference to a resource, which requires no locking.
+    pub fn get_mut<T: Resource, Q>(&mut self, k: &Q) -> Option<&mut T>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.resources
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|m| m.get_mut(k))
+            .and_then(|b| b.downcast_mut::<RwLock<T>>())
+            .map(RwLock::get_mut)
+    }
+
+    /// Removes a resource from the world.
+    pub fn remove<T: Resource, Q>(&mut self, k: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        let map = self.resources.get_mut(&TypeId::of::<T>())?;
+        let ret = map.remove(k).map(|b| Self::downcast::<T>(b).into_inner());
+
+        if map.is_empty() {
+            self.resources.remove(&TypeId::of::<T>());
+        }
+
+        ret
+    }
+
+    fn downcast<T: Resource>(b: Box<dyn Resource>) -> RwLock<T> {
+        *b.downcast().ok().expect("Unreachable")
+    }
+}
+
+fn first<G>(result: LockResult<(G,)>) -> LockResult<G> {
+    result
+        .map(|(guard,)| guard)
+        .map_err(|e| PoisonError::new(e.into_inner().0))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use nitric_lock::lock;
+
+    use super::*;
+
+    #[test]
+    fn insert_lock_remove() {
+        let mut world = SyncWorld::new();
+        assert_eq!(world.insert("a", 1u32), None);
+        assert_eq!(world.insert("a", 2u32), Some(1));
+
+        {
+            let mut token = world.token();
+            *world.write::<u32, _>(&mut token, "a").unwrap() += 1;
+            assert_eq!(*world.read::<u32, _>(&mut token, "a").unwrap(), 3);
+        }
+
+        assert!(world.get_lock::<u64, _>("a").is_none());
+        match world.read::<u32, _>(&mut world.token(), "b") {
+            Err(LockError::Fetch(e)) => assert_eq!(e, FetchError::Missing { type_name: "u32" }),
+            _ => panic!("Expected a missing resource"),
+        }
+        assert_eq!(
+            world.try_get_lock::<u32, _>("b").err(),
+            Some(FetchError::Missing { type_name: "u32" })
+        );
+        assert_eq!(world.get_mut::<u32, _>("a"), Some(&mut 3));
+        assert_eq!(world.remove::<u32, _>("a"), Some(3));
+        assert_eq!(world.remove::<u32, _>("a"), None);
+    }
+
+    #[test]
+    fn threads() {
+        let mut world = SyncWorld::new();
+        world.insert("a", 0u32);
+        world.insert("b", 0u32);
+        let world = Arc::new(world);
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let world = world.clone();
+
+                thread::spawn(move || {
+                    let mut token = world.token();
+                    let a = world.get_lock::<u32, _>("a").unwrap();
+                    let b = world.get_lock::<u32, _>("b").unwrap();
+
+                    for _ in 0..100 {
+                        // Alternate the order, which must not deadlock.
+                        let (mut x, mut y) = match i % 2 {
+                            0 => lock!(&mut token, a.write(), b.write()).unwrap(),
+                            _ => {
+                                let (y, x) = lock!(&mut token, b.write(), a.write()).unwrap();
+
+                                (x, y)
+                            }
+                        };
+
+                        *x += 1;
+                        *y += 2;
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut token = world.token();
+        assert_eq!(*world.read::<u32, _>(&mut token, "a").unwrap(), 400);
+        assert_eq!(*world.read::<u32, _>(&mut token, "b").unwrap(), 800);
+    }
+}
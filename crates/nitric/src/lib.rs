@@ -2,6 +2,14 @@
 #[doc(inline)]
 pub use nitric_component as component;
 
+#[cfg(feature = "event")]
+#[doc(inline)]
+pub use nitric_event as event;
+
+#[cfg(feature = "graph")]
+#[doc(inline)]
+pub use nitric_graph as graph;
+
 #[cfg(feature = "lock")]
 #[doc(inline)]
 pub use nitric_lock as lock;